@@ -1,5 +1,16 @@
 fn main() {
+    // 主机上运行测试时不需要链接脚本
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("none") {
+        return
+    }
+
+    // 使用自己的memory.x，为配置存储保留闪存末尾的空间
+    let out_dir = std::path::PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+    std::fs::write(out_dir.join("memory.x"), include_bytes!("memory.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out_dir.display());
+    println!("cargo:rerun-if-changed=memory.x");
+
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
-}
\ No newline at end of file
+}
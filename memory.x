@@ -0,0 +1,6 @@
+/* STM32F103C8，闪存末尾4K保留给配置存储(见kbd_cfg::storage) */
+MEMORY
+{
+    FLASH : ORIGIN = 0x08000000, LENGTH = 60K
+    RAM   : ORIGIN = 0x20000000, LENGTH = 20K
+}
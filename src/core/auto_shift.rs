@@ -0,0 +1,84 @@
+// 自动Shift
+// 轻击字母/数字键输出原字符，按住超过阈值输出Shift后的字符
+// 待定的按键按按下顺序排队，保证输出顺序与按下顺序一致
+
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+
+use super::kbd::key::QwertyKey;
+use super::scheduler::{Scheduler, Task};
+use crate::kbd_cfg::core::{AUTO_SHIFT_ENABLED, AUTO_SHIFT_EXCLUDE, AUTO_SHIFT_MAX, AUTO_SHIFT_TIMEOUT_MS};
+
+/// 待定的按键
+#[derive(Debug, Clone, Copy)]
+pub struct AutoShiftKey {
+    pub key_index: usize,
+    pub qwerty_key: QwertyKey,
+    pub deadline: Instant,
+}
+
+pub struct AutoShift {
+    enabled: bool,
+    pending: Deque<AutoShiftKey, AUTO_SHIFT_MAX>,
+}
+
+impl Default for AutoShift {
+    fn default() -> Self {
+        Self { enabled: AUTO_SHIFT_ENABLED, pending: Deque::new() }
+    }
+}
+
+impl AutoShift {
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// 是否对该键启用自动Shift，按住其他修饰键时不启用
+    pub fn is_eligible(&self, qwerty_key: QwertyKey, modifier: u8) -> bool {
+        use QwertyKey::*;
+        self.enabled
+            && modifier == 0
+            && (A as u8..=Kc0 as u8).contains(&(qwerty_key as u8))
+            && !AUTO_SHIFT_EXCLUDE.contains(&qwerty_key)
+    }
+
+    /// 按下按键，队列已满时返回`false`，调用方需按普通键处理
+    pub fn on_press(&mut self, key_index: usize, qwerty_key: QwertyKey, now: Instant, scheduler: &mut Scheduler) -> bool {
+        let deadline = now + Duration::from_millis(AUTO_SHIFT_TIMEOUT_MS);
+        let pushed = self.pending.push_back(AutoShiftKey { key_index, qwerty_key, deadline }).is_ok();
+        self.reschedule(scheduler);
+        pushed
+    }
+
+    pub fn is_pending(&self, key_index: usize) -> bool {
+        self.pending.iter().any(|key| key.key_index == key_index)
+    }
+
+    /// 最早按下的待定键
+    pub fn pop_front(&mut self, scheduler: &mut Scheduler) -> Option<AutoShiftKey> {
+        let key = self.pending.pop_front();
+        self.reschedule(scheduler);
+        key
+    }
+
+    /// 最早按下且已超时的待定键
+    pub fn pop_expired(&mut self, now: Instant, scheduler: &mut Scheduler) -> Option<AutoShiftKey> {
+        match self.pending.front() {
+            Some(key) if key.deadline <= now => self.pop_front(scheduler),
+            _ => None,
+        }
+    }
+
+    /// 按最早按下的待定键登记超时任务
+    fn reschedule(&self, scheduler: &mut Scheduler) {
+        scheduler.update(self.pending.front().map(|key| key.deadline), Task::AutoShiftTimeout);
+    }
+}
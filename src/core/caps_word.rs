@@ -0,0 +1,126 @@
+// Caps Word
+// 激活后字母(及`-`)自动加Shift，遇到断词键、空闲超时或再次按下时结束，不影响主机的CapsLock状态
+
+use embassy_time::{Duration, Instant};
+
+use super::kbd::key::{KbdKey, ModifierKey, QwertyKey};
+use super::scheduler::{Scheduler, Task};
+use crate::kbd_cfg::core::{CAPS_WORD_BREAK, CAPS_WORD_CONTINUE, CAPS_WORD_IDLE_TIMEOUT_MS};
+
+#[derive(Default)]
+pub struct CapsWord {
+    /// 激活时为空闲超时时间
+    deadline: Option<Instant>,
+}
+
+impl CapsWord {
+    pub fn is_active(&self) -> bool {
+        self.deadline.is_some()
+    }
+
+    pub fn toggle(&mut self, now: Instant, scheduler: &mut Scheduler) {
+        self.deadline = match self.deadline {
+            Some(_) => None,
+            None => Some(now + Duration::from_millis(CAPS_WORD_IDLE_TIMEOUT_MS)),
+        };
+        scheduler.update(self.deadline, Task::CapsWordTimeout);
+    }
+
+    /// 空闲超时
+    pub fn on_timeout(&mut self, now: Instant) {
+        if self.deadline.is_some_and(|deadline| deadline <= now) {
+            self.deadline = None;
+        }
+    }
+
+    /// 按下按键，返回该键是否需要加Shift
+    ///
+    /// 0. 修饰键、切层键和媒体键不影响Caps Word(与QMK相同，可以按住修饰键后再松开)
+    /// 1. 字母和`-`加Shift后继续
+    /// 2. `CAPS_WORD_CONTINUE`中的键不加Shift，继续
+    /// 3. 其他键、`CAPS_WORD_BREAK`中的键以及按住Shift以外的修饰键时结束
+    ///
+    /// 主机已开启CapsLock时字母本身就是大写，不再加Shift(否则会变回小写)
+    pub fn on_key(&mut self, kbd_key: KbdKey, modifier: u8, caps_lock: bool, now: Instant, scheduler: &mut Scheduler) -> bool {
+        let KbdKey::Normal(qwerty_key) = kbd_key else { return false };
+        if !self.is_active() {
+            return false
+        }
+        let shifted = self.shift_key(qwerty_key, modifier, caps_lock, now);
+        scheduler.update(self.deadline, Task::CapsWordTimeout);
+        shifted
+    }
+
+    fn shift_key(&mut self, qwerty_key: QwertyKey, modifier: u8, caps_lock: bool, now: Instant) -> bool {
+        use QwertyKey::*;
+
+        let shift_mask = ModifierKey::LShift.bit() | ModifierKey::RShift.bit();
+        let is_letter = (A as u8..=Z as u8).contains(&(qwerty_key as u8));
+        let is_shifted_key = is_letter || qwerty_key == Minus;
+        if modifier & !shift_mask != 0 || CAPS_WORD_BREAK.contains(&qwerty_key) {
+            self.deadline = None;
+            return false
+        }
+        if is_shifted_key || CAPS_WORD_CONTINUE.contains(&qwerty_key) {
+            self.deadline = Some(now + Duration::from_millis(CAPS_WORD_IDLE_TIMEOUT_MS));
+            return is_shifted_key && !(is_letter && caps_lock)
+        }
+        self.deadline = None;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::kbd::key::ModifierKey::*;
+    use crate::core::kbd::key::QwertyKey::*;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn active(now: Instant, scheduler: &mut Scheduler) -> CapsWord {
+        let mut caps_word = CapsWord::default();
+        caps_word.toggle(now, scheduler);
+        caps_word
+    }
+
+    #[test]
+    fn modifier_press_keeps_word() {
+        let mut scheduler = Scheduler::default();
+        let mut caps_word = active(at(0), &mut scheduler);
+        assert!(caps_word.on_key(A.into(), 0, false, at(10), &mut scheduler));
+        // 单独按下Shift、Ctrl不结束
+        assert!(!caps_word.on_key(LShift.into(), 0, false, at(20), &mut scheduler));
+        assert!(!caps_word.on_key(LCtrl.into(), LShift.bit(), false, at(30), &mut scheduler));
+        assert!(caps_word.is_active());
+        // 按住Shift输入字母继续
+        assert!(caps_word.on_key(B.into(), LShift.bit(), false, at(40), &mut scheduler));
+        // 松开Ctrl后继续
+        assert!(caps_word.on_key(C.into(), 0, false, at(50), &mut scheduler));
+        assert!(caps_word.is_active());
+    }
+
+    #[test]
+    fn letter_with_ctrl_ends_word() {
+        let mut scheduler = Scheduler::default();
+        let mut caps_word = active(at(0), &mut scheduler);
+        assert!(!caps_word.on_key(LCtrl.into(), 0, false, at(10), &mut scheduler));
+        assert!(caps_word.is_active());
+        assert!(!caps_word.on_key(C.into(), LCtrl.bit(), false, at(20), &mut scheduler));
+        assert!(!caps_word.is_active());
+    }
+
+    #[test]
+    fn break_key_and_timeout() {
+        let mut scheduler = Scheduler::default();
+        let mut caps_word = active(at(0), &mut scheduler);
+        assert!(!caps_word.on_key(Space.into(), 0, false, at(10), &mut scheduler));
+        assert!(!caps_word.is_active());
+
+        let mut caps_word = active(at(0), &mut scheduler);
+        caps_word.on_timeout(at(CAPS_WORD_IDLE_TIMEOUT_MS));
+        assert!(!caps_word.is_active());
+    }
+}
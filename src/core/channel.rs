@@ -1,11 +1,38 @@
-use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+#[cfg(not(test))]
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex as ChannelMutex;
+// 主机测试在多个线程中运行，线程模式锁只能在主线程使用
+#[cfg(test)]
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex as ChannelMutex;
 use embassy_sync::channel::Channel;
-use usbd_hid::descriptor::KeyboardReport;
+use usbd_hid::descriptor::MouseReport;
 use crate::core::kbd::key_event::KeyEvent;
+use crate::core::extra_report::ExtraReport;
+use crate::core::kbd_report::KbdReport;
+// 主机LED和VIA只在USB与核心主循环之间传递
+#[cfg(not(test))]
+use {
+    embassy_sync::watch::Watch,
+    crate::core::host_leds::HostLeds,
+    crate::core::via::ViaPacket,
+    crate::kbd_cfg::channel::HOST_LEDS_RECEIVER_NUM,
+};
 
 use crate::kbd_cfg::channel::{KEY_EVENT_CHANNEL_SIZE, REPORT_CHANNEL_SIZE};
 
 /// 按键事件
-pub static KEY_EVENT_CHANNEL: Channel<ThreadModeRawMutex, KeyEvent, KEY_EVENT_CHANNEL_SIZE> = Channel::new();
+pub static KEY_EVENT_CHANNEL: Channel<ChannelMutex, KeyEvent, KEY_EVENT_CHANNEL_SIZE> = Channel::new();
 /// 按键报告
-pub static KEYBOARD_REPORT_CHANNEL: Channel<ThreadModeRawMutex, KeyboardReport, REPORT_CHANNEL_SIZE> = Channel::new();
\ No newline at end of file
+pub static KEYBOARD_REPORT_CHANNEL: Channel<ChannelMutex, KbdReport, REPORT_CHANNEL_SIZE> = Channel::new();
+/// 非键盘报告(媒体键等)
+pub static EXTRA_REPORT_CHANNEL: Channel<ChannelMutex, ExtraReport, REPORT_CHANNEL_SIZE> = Channel::new();
+/// 鼠标报告
+pub static MOUSE_REPORT_CHANNEL: Channel<ChannelMutex, MouseReport, REPORT_CHANNEL_SIZE> = Channel::new();
+/// 主机LED状态
+#[cfg(not(test))]
+pub static HOST_LEDS_WATCH: Watch<ChannelMutex, HostLeds, HOST_LEDS_RECEIVER_NUM> = Watch::new();
+/// VIA命令，USB收到后交给核心处理
+#[cfg(not(test))]
+pub static VIA_REQUEST_CHANNEL: Channel<ChannelMutex, ViaPacket, 1> = Channel::new();
+/// VIA应答
+#[cfg(not(test))]
+pub static VIA_RESPONSE_CHANNEL: Channel<ChannelMutex, ViaPacket, 1> = Channel::new();
\ No newline at end of file
@@ -0,0 +1,300 @@
+// 组合键(Combo)判定逻辑
+// 成员键按下时开始判定，判定期间的按键事件暂存在事件队列中：
+// 1. 构成组合键时，用虚拟按键事件(索引为KEY_NUM+组合键索引)代替暂存的成员键按下事件
+// 2. 无法构成组合键时，按原顺序重放暂存的事件，其中第一个事件不再开始判定
+// 判定在处理事件时进行，使用当时的层状态；与IO无关，由调用方暂存和重放事件
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use super::kbd::key_action::Combo;
+use super::kbd::key_event::KeyEvent;
+use super::layer_state::LayerState;
+use super::scheduler::{Scheduler, Task};
+use crate::kbd_cfg::core::{COMBO_MAX, COMBO_MAX_KEYS};
+
+/// 判定结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComboDecision {
+    /// 还未确定，继续暂存事件
+    Pending,
+    /// 触发组合键，最先暂存的`key_num`个成员键按下事件由虚拟按键事件`event`代替
+    Fired { event: KeyEvent, key_num: usize },
+    /// 无法构成组合键，按原顺序重放暂存的事件
+    Failed,
+}
+
+/// 已触发的组合键
+#[derive(Debug, Clone, Copy)]
+struct ActiveCombo {
+    /// 仍按住的成员键，第n位对应第n个成员键
+    held: u8,
+    /// 是否已发送虚拟松开事件
+    released: bool,
+}
+
+pub struct Combos<const KEY_NUM: usize> {
+    /// 判定中已按下的成员键，按按下顺序排列
+    pending: Vec<u8, COMBO_MAX_KEYS>,
+    /// 第一个/最后一个成员键按下的时间
+    first_press: Instant,
+    last_press: Instant,
+    /// 判定失败后重放的第一个按下事件，处理该事件时不再开始判定
+    bypass: Option<KeyEvent>,
+    /// 已触发的组合键，下标为组合键索引
+    active: [Option<ActiveCombo>; COMBO_MAX],
+}
+
+impl<const KEY_NUM: usize> Default for Combos<KEY_NUM> {
+    fn default() -> Self {
+        Self {
+            pending: Vec::new(),
+            first_press: Instant::MIN,
+            last_press: Instant::MIN,
+            bypass: None,
+            active: [None; COMBO_MAX],
+        }
+    }
+}
+
+impl<const KEY_NUM: usize> Combos<KEY_NUM> {
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// 虚拟按键对应的组合键索引
+    pub fn combo_index(key_index: usize) -> Option<usize> {
+        key_index.checked_sub(KEY_NUM)
+    }
+
+    /// 按下按键，是当前层可用组合键的成员键时开始判定并返回`true`，调用方需暂存该事件
+    ///
+    /// 开始判定时登记超时任务
+    pub fn on_press(&mut self, combos: &[Combo], layer_state: &LayerState, event: &KeyEvent, scheduler: &mut Scheduler) -> bool {
+        if self.bypass.take() == Some(*event) {
+            return false
+        }
+
+        let key_index = event.key_index;
+        let timeout_ms = combos.iter()
+            .filter(|combo| Self::is_available(combo, layer_state) && combo.keys().contains(&key_index))
+            .map(|combo| combo.timeout_ms)
+            .max();
+        let Some(timeout_ms) = timeout_ms else { return false };
+
+        self.pending.clear();
+        let _ = self.pending.push(key_index);
+        self.first_press = event.time;
+        self.last_press = event.time;
+        scheduler.schedule(event.time + Duration::from_millis(timeout_ms as u64), Task::ComboTimeout);
+        true
+    }
+
+    /// 判定期间的按键事件(调用方已暂存该事件)，判定完成时取消超时任务
+    pub fn on_event(&mut self, combos: &[Combo], layer_state: &LayerState, event: &KeyEvent, scheduler: &mut Scheduler) -> ComboDecision {
+        let decision = self.decide(combos, layer_state, event);
+        if decision != ComboDecision::Pending {
+            scheduler.cancel(Task::ComboTimeout);
+        }
+        decision
+    }
+
+    fn decide(&mut self, combos: &[Combo], layer_state: &LayerState, event: &KeyEvent) -> ComboDecision {
+        // 尝试加入判定
+        if event.is_pressed && !self.pending.contains(&event.key_index) && self.pending.push(event.key_index).is_ok() {
+            let last_press = self.last_press;
+            self.last_press = event.time;
+            if self.candidates(combos, layer_state).next().is_some() {
+                return self.fire_if_complete(combos, layer_state)
+            }
+            self.pending.pop();
+            self.last_press = last_press;
+        }
+        self.resolve(combos, layer_state)
+    }
+
+    /// 判定超时
+    pub fn on_timeout(&mut self, combos: &[Combo], layer_state: &LayerState) -> ComboDecision {
+        self.resolve(combos, layer_state)
+    }
+
+    /// 松开按键，已触发组合键的成员键松开时转换为虚拟按键松开事件
+    ///
+    /// 返回需要继续处理的事件，`None`表示该事件被丢弃
+    pub fn on_release(&mut self, combos: &[Combo], event: KeyEvent) -> Option<KeyEvent> {
+        let key_index = event.key_index;
+        for (combo_index, slot) in self.active.iter_mut().enumerate() {
+            let Some(active) = slot else { continue };
+            let Some(pos) = combos[combo_index].keys().iter().position(|&k| k == key_index) else { continue };
+            if (active.held >> pos) & 1 == 0 {
+                continue;
+            }
+
+            active.held &= !(1 << pos);
+            // 任一成员键松开即松开组合键，其余成员键的松开事件直接丢弃
+            let released = core::mem::replace(&mut active.released, true);
+            if active.held == 0 {
+                *slot = None;
+            }
+            return (!released).then(|| KeyEvent::new(false, (KEY_NUM + combo_index) as u8, event.time))
+        }
+        Some(event)
+    }
+
+    /// 已按下的成员键恰好构成组合键，且不可能再构成更大的组合键时触发
+    fn fire_if_complete(&mut self, combos: &[Combo], layer_state: &LayerState) -> ComboDecision {
+        let mut complete = None;
+        for (combo_index, combo) in self.candidates(combos, layer_state) {
+            if combo.keys().len() != self.pending.len() {
+                return ComboDecision::Pending
+            }
+            complete.get_or_insert(combo_index);
+        }
+        match complete {
+            Some(combo_index) => self.fire(combos, combo_index),
+            None => ComboDecision::Pending,
+        }
+    }
+
+    /// 结束判定：恰好构成组合键则触发，否则重放暂存的事件
+    fn resolve(&mut self, combos: &[Combo], layer_state: &LayerState) -> ComboDecision {
+        let complete = self.candidates(combos, layer_state)
+            .find(|(_, combo)| combo.keys().len() == self.pending.len())
+            .map(|(combo_index, _)| combo_index);
+        match complete {
+            Some(combo_index) => self.fire(combos, combo_index),
+            None => {
+                self.bypass = self.pending.first().map(|&key_index| KeyEvent::new(true, key_index, self.first_press));
+                self.pending.clear();
+                ComboDecision::Failed
+            },
+        }
+    }
+
+    fn fire(&mut self, combos: &[Combo], combo_index: usize) -> ComboDecision {
+        let key_num = combos[combo_index].keys().len();
+        self.active[combo_index] = Some(ActiveCombo { held: (1u8 << key_num) - 1, released: false });
+        self.pending.clear();
+        let event = KeyEvent::new(true, (KEY_NUM + combo_index) as u8, self.last_press);
+        ComboDecision::Fired { event, key_num }
+    }
+
+    /// 包含所有已按下的成员键、且在判定窗口内的组合键
+    fn candidates<'a>(&'a self, combos: &'a [Combo], layer_state: &'a LayerState) -> impl Iterator<Item = (usize, &'a Combo)> + 'a {
+        let elapsed = self.last_press - self.first_press;
+        combos.iter().enumerate().filter(move |(combo_index, combo)| {
+            Self::is_available(combo, layer_state)
+                && self.active[*combo_index].is_none()
+                && elapsed <= Duration::from_millis(combo.timeout_ms as u64)
+                && self.pending.iter().all(|key_index| combo.keys().contains(key_index))
+        })
+    }
+
+    fn is_available(combo: &Combo, layer_state: &LayerState) -> bool {
+        combo.layer.is_none_or(|layer| layer_state.is_active(layer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::kbd::key::basic_key::*;
+    use crate::core::kbd::key_action::{ck, KeyAction};
+
+    const KEY_NUM: usize = 8;
+
+    fn event(is_pressed: bool, key_index: u8, ms: u64) -> KeyEvent {
+        KeyEvent::new(is_pressed, key_index, Instant::from_millis(ms))
+    }
+
+    fn combos() -> [Combo; 2] {
+        [
+            Combo::new(&[1, 2], ck(A)),
+            Combo::new(&[1, 2, 3], ck(B)),
+        ]
+    }
+
+    #[test]
+    fn fires_when_complete() {
+        let combos = [Combo::new(&[1, 2], ck(A))];
+        let layer_state = LayerState::default();
+        let mut state = Combos::<KEY_NUM>::default();
+        let mut scheduler = Scheduler::default();
+        assert!(state.on_press(&combos, &layer_state, &event(true, 1, 0), &mut scheduler));
+        let decision = state.on_event(&combos, &layer_state, &event(true, 2, 10), &mut scheduler);
+        assert_eq!(decision, ComboDecision::Fired { event: event(true, KEY_NUM as u8, 10), key_num: 2 });
+        assert!(!state.is_pending());
+        assert_eq!(scheduler.next_deadline(), None);
+
+        // 第一个成员键松开即松开组合键，另一个成员键的松开被丢弃
+        assert_eq!(state.on_release(&combos, event(false, 2, 50)), Some(event(false, KEY_NUM as u8, 50)));
+        assert_eq!(state.on_release(&combos, event(false, 1, 60)), None);
+        assert_eq!(state.on_release(&combos, event(false, 1, 70)), Some(event(false, 1, 70)));
+    }
+
+    #[test]
+    fn waits_for_larger_combo() {
+        let combos = combos();
+        let layer_state = LayerState::default();
+        let mut state = Combos::<KEY_NUM>::default();
+        let mut scheduler = Scheduler::default();
+        state.on_press(&combos, &layer_state, &event(true, 1, 0), &mut scheduler);
+        // 还可能构成更大的组合键，继续等待
+        assert_eq!(state.on_event(&combos, &layer_state, &event(true, 2, 10), &mut scheduler), ComboDecision::Pending);
+        // 超时时恰好构成较小的组合键
+        let decision = state.on_timeout(&combos, &layer_state);
+        assert_eq!(decision, ComboDecision::Fired { event: event(true, KEY_NUM as u8, 10), key_num: 2 });
+
+        // 其他键打断时同样按已按下的成员键判定，打断的事件由调用方重放
+        state.on_release(&combos, event(false, 1, 20));
+        state.on_release(&combos, event(false, 2, 20));
+        state.on_press(&combos, &layer_state, &event(true, 1, 100), &mut scheduler);
+        state.on_event(&combos, &layer_state, &event(true, 2, 110), &mut scheduler);
+        let decision = state.on_event(&combos, &layer_state, &event(true, 5, 120), &mut scheduler);
+        assert_eq!(decision, ComboDecision::Fired { event: event(true, KEY_NUM as u8, 110), key_num: 2 });
+    }
+
+    #[test]
+    fn failed_combo_replays_without_restarting() {
+        let combos = combos();
+        let layer_state = LayerState::default();
+        let mut state = Combos::<KEY_NUM>::default();
+        let mut scheduler = Scheduler::default();
+        let first = event(true, 1, 0);
+        state.on_press(&combos, &layer_state, &first, &mut scheduler);
+        assert_eq!(state.on_event(&combos, &layer_state, &event(false, 1, 30), &mut scheduler), ComboDecision::Failed);
+        // 重放的第一个事件不再开始判定，之后的按下照常判定
+        assert!(!state.on_press(&combos, &layer_state, &first, &mut scheduler));
+        assert!(state.on_press(&combos, &layer_state, &event(true, 1, 100), &mut scheduler));
+    }
+
+    #[test]
+    fn timeout_ignores_inactive_layer() {
+        let combos = [
+            Combo::new(&[1, 2], ck(A)).timeout(50),
+            Combo::new(&[1, 3], ck(B)).timeout(500).layer(1),
+        ];
+        let layer_state = LayerState::default();
+        let mut state = Combos::<KEY_NUM>::default();
+        let mut scheduler = Scheduler::default();
+        state.on_press(&combos, &layer_state, &event(true, 1, 0), &mut scheduler);
+        // 层1未激活，判定窗口只取可用的组合键
+        assert_eq!(scheduler.next_deadline(), Some(Instant::from_millis(50)));
+        // 只属于不可用组合键的按键不开始判定
+        assert!(!state.on_press(&combos, &layer_state, &event(true, 3, 0), &mut scheduler));
+    }
+
+    #[test]
+    fn timeout_without_pending_keys() {
+        let combos = combos();
+        let mut state = Combos::<KEY_NUM>::default();
+        assert_eq!(state.on_timeout(&combos, &LayerState::default()), ComboDecision::Failed);
+        assert_eq!(state.on_release(&combos, event(false, 1, 10)), Some(event(false, 1, 10)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn transparent_action_rejected() {
+        Combo::new(&[1, 2], KeyAction::TS);
+    }
+}
@@ -0,0 +1,93 @@
+// 按键事件队列
+// 位于KEY_EVENT_CHANNEL与process_event之间，收到的事件先放入队列再按顺序处理
+// 待定判定(待定键、轻击舞、组合键)期间的事件暂存起来，判定结束后按原顺序放回队首重放
+
+use heapless::{Deque, Vec};
+
+use super::kbd::key_event::KeyEvent;
+use crate::kbd_cfg::core::EVENT_QUEUE_SIZE;
+
+pub struct EventQueue {
+    /// 待处理的事件
+    events: Deque<KeyEvent, EVENT_QUEUE_SIZE>,
+    /// 待定判定期间暂存的事件，与`events`共用`EVENT_QUEUE_SIZE`的容量
+    held: Vec<KeyEvent, EVENT_QUEUE_SIZE>,
+}
+
+impl EventQueue {
+    pub const fn new() -> Self {
+        Self { events: Deque::new(), held: Vec::new() }
+    }
+
+    /// 待处理和暂存的事件已占满容量，需先结束待定判定再接收新事件
+    pub fn is_full(&self) -> bool {
+        self.events.len() + self.held.len() >= EVENT_QUEUE_SIZE
+    }
+
+    pub fn push_back(&mut self, event: KeyEvent) {
+        if self.is_full() || self.events.push_back(event).is_err() {
+            defmt::warn!("Event queue full, drop key event `{}`", event.key_index);
+        }
+    }
+
+    /// 重放事件，按原顺序排在队首，先于之后收到的事件处理
+    pub fn replay(&mut self, events: &[KeyEvent]) {
+        for &event in events.iter().rev() {
+            if self.events.push_front(event).is_err() {
+                defmt::warn!("Event queue full, drop key event `{}`", event.key_index);
+            }
+        }
+    }
+
+    /// 下一个待处理的事件
+    pub fn front(&self) -> Option<&KeyEvent> {
+        self.events.front()
+    }
+
+    pub fn pop_front(&mut self) -> Option<KeyEvent> {
+        self.events.pop_front()
+    }
+
+    /// 暂存待定判定期间取出的事件
+    pub fn hold(&mut self, event: KeyEvent) {
+        if self.held.push(event).is_err() {
+            defmt::warn!("Event queue full, drop key event `{}`", event.key_index);
+        }
+    }
+
+    /// 丢弃最先暂存的`count`个事件(如已被组合键代替的成员键按下事件)
+    pub fn discard_held(&mut self, count: usize) {
+        let count = count.min(self.held.len());
+        self.held.rotate_left(count);
+        self.held.truncate(self.held.len() - count);
+    }
+
+    /// 待定判定结束，暂存的事件按原顺序放回队首
+    pub fn replay_held(&mut self) {
+        let held = core::mem::take(&mut self.held);
+        self.replay(&held);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_time::Instant;
+
+    fn press(key_index: u8) -> KeyEvent {
+        KeyEvent::new(true, key_index, Instant::from_millis(0))
+    }
+
+    #[test]
+    fn held_events_replay_before_queued() {
+        let mut queue = EventQueue::new();
+        queue.push_back(press(9));
+        for key_index in 1..=4 {
+            queue.hold(press(key_index));
+        }
+        queue.discard_held(2);
+        queue.replay_held();
+        let order: Vec<u8, EVENT_QUEUE_SIZE> = core::iter::from_fn(|| queue.pop_front()).map(|e| e.key_index).collect();
+        assert_eq!(order, [3, 4, 9]);
+    }
+}
@@ -0,0 +1,111 @@
+// 非键盘报文
+// 媒体键等不属于键盘用法页的按键共用一个HID接口，通过报文ID区分报文类型
+
+use super::kbd::key::{KbdKey, QwertyKey};
+
+/// 报文ID，需与HID报文描述符一致
+pub const CONSUMER_REPORT_ID: u8 = 1;
+pub const SYSTEM_REPORT_ID: u8 = 2;
+
+/// 报文类型数
+pub const EXTRA_REPORT_NUM: usize = 2;
+
+/// 带报文ID的最大报文长度
+#[cfg(not(test))]
+pub const EXTRA_REPORT_SIZE: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtraReport {
+    /// Consumer Page用法，0表示松开
+    Consumer(u16),
+    /// Generic Desktop Page的System Control用法(电源、睡眠、唤醒)，0表示松开
+    System(u16),
+}
+
+impl ExtraReport {
+    pub fn report_id(&self) -> u8 {
+        match self {
+            ExtraReport::Consumer(_) => CONSUMER_REPORT_ID,
+            ExtraReport::System(_) => SYSTEM_REPORT_ID,
+        }
+    }
+
+    pub fn usage(&self) -> u16 {
+        match *self {
+            ExtraReport::Consumer(usage) | ExtraReport::System(usage) => usage,
+        }
+    }
+
+    /// 同类型、用法为0的松开报文
+    pub fn released(&self) -> Self {
+        match self {
+            ExtraReport::Consumer(_) => ExtraReport::Consumer(0),
+            ExtraReport::System(_) => ExtraReport::System(0),
+        }
+    }
+
+    /// 按键对应的报文，不属于非键盘报文时返回`None`
+    pub fn from_kbd_key(kbd_key: KbdKey) -> Option<Self> {
+        match kbd_key {
+            KbdKey::Normal(qwerty_key) => consumer_usage(qwerty_key).map(ExtraReport::Consumer)
+                .or_else(|| system_usage(qwerty_key).map(ExtraReport::System)),
+            KbdKey::Consumer(usage) => Some(ExtraReport::Consumer(usage)),
+            KbdKey::State(_) => None,
+        }
+    }
+
+    /// 序列化为带报文ID的报文，返回报文长度
+    #[cfg(not(test))]
+    pub fn serialize(&self, buf: &mut [u8; EXTRA_REPORT_SIZE]) -> usize {
+        buf[0] = self.report_id();
+        buf[1..3].copy_from_slice(&self.usage().to_le_bytes());
+        3
+    }
+}
+
+/// `QwertyKey`中的系统控制键对应的System Control用法
+pub fn system_usage(qwerty_key: QwertyKey) -> Option<u16> {
+    use QwertyKey::*;
+    match qwerty_key {
+        SystemPower => Some(0x81),
+        SystemSleep => Some(0x82),
+        SystemWake => Some(0x83),
+        _ => None,
+    }
+}
+
+/// `QwertyKey`中的媒体键对应的Consumer Page用法，主机会忽略键盘报文中的这些按键
+pub fn consumer_usage(qwerty_key: QwertyKey) -> Option<u16> {
+    use QwertyKey::*;
+    let usage = match qwerty_key {
+        AudioMute => 0x00E2,
+        AudioVolUp => 0x00E9,
+        AudioVolDown => 0x00EA,
+        MediaNextTrack => 0x00B5,
+        MediaPrevTrack => 0x00B6,
+        MediaStop => 0x00B7,
+        MediaPlayPause => 0x00CD,
+        MediaSelect => 0x0183,
+        MediaEject => 0x00B8,
+        Mail => 0x018A,
+        Calculator => 0x0192,
+        MyComputer => 0x0194,
+        WwwSearch => 0x0221,
+        WwwHome => 0x0223,
+        WwwBack => 0x0224,
+        WwwForward => 0x0225,
+        WwwStop => 0x0226,
+        WwwRefresh => 0x0227,
+        WwwFavorites => 0x022A,
+        MediaFastForward => 0x00B3,
+        MediaRewind => 0x00B4,
+        BrightnessUp => 0x006F,
+        BrightnessDown => 0x0070,
+        ControlPanel => 0x019F,
+        Assistant => 0x01CB,
+        MissionControl => 0x029F,
+        Launchpad => 0x02A0,
+        _ => return None,
+    };
+    Some(usage)
+}
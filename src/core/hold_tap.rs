@@ -0,0 +1,247 @@
+// 待定键(Hold-Tap)判定逻辑
+// 同一时间只有一个待定键在判定，判定期间的其他按键事件暂存在事件队列中：
+// 1. 每个事件按判定策略推进判定，待定键松开时判定为轻击，超时(定时任务)判定为按住
+// 2. 判定完成后先输出判定结果，再按原顺序重放暂存的事件(包括之后按下的其他待定键)
+// 与IO无关，只根据按键事件和时间推进状态，方便单独测试
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use super::kbd::key_action::{HoldTap, HoldTapFlavor};
+use super::kbd::key_event::KeyEvent;
+use super::scheduler::{Scheduler, Task};
+use crate::kbd_cfg::core::HOLD_TAP_BUFFER_SIZE;
+
+/// 判定结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoldTapDecision {
+    /// 还未确定，继续等待
+    Pending,
+    /// 轻击，触发`qwerty_key`
+    Tap,
+    /// 按住，触发`state_key`
+    Hold,
+}
+
+pub struct HoldTapState {
+    /// 待定键的按键索引
+    pub key_index: usize,
+    pub hold_tap: HoldTap,
+    /// 在待定键之后按下的其他键，这些键松开时才算完成一次轻击
+    pressed_after: Vec<u8, HOLD_TAP_BUFFER_SIZE>,
+}
+
+impl HoldTapState {
+    /// 开始判定，登记超时任务，`tapping_term_ms`为`None`时不超时
+    pub fn new(key_index: usize, hold_tap: HoldTap, now: Instant, scheduler: &mut Scheduler) -> Self {
+        let deadline = hold_tap.tapping_term_ms.map(|ms| now + Duration::from_millis(ms as u64));
+        scheduler.update(deadline, Task::HoldTapTimeout);
+        Self { key_index, hold_tap, pressed_after: Vec::new() }
+    }
+
+    /// 处理判定期间的按键事件，判定完成时取消超时任务
+    ///
+    /// 待定键松开时判定为轻击，该松开事件随轻击一起输出，其余事件由调用方在判定后重放
+    pub fn on_event(&mut self, event: &KeyEvent, scheduler: &mut Scheduler) -> HoldTapDecision {
+        let decision = self.decide(event);
+        if decision != HoldTapDecision::Pending {
+            scheduler.cancel(Task::HoldTapTimeout);
+        }
+        decision
+    }
+
+    fn decide(&mut self, event: &KeyEvent) -> HoldTapDecision {
+        if event.key_index as usize == self.key_index {
+            return match event.is_pressed {
+                true => HoldTapDecision::Pending,
+                false => HoldTapDecision::Tap,
+            }
+        }
+        self.on_other_event(event.is_pressed, event.key_index)
+    }
+
+    /// 其他键的事件，根据判定策略决定是否判定为按住
+    fn on_other_event(&mut self, is_pressed: bool, key_index: u8) -> HoldTapDecision {
+        let is_hold = if is_pressed {
+            if self.pressed_after.push(key_index).is_err() {
+                defmt::warn!("Too many keys pressed during hold-tap, resolve as hold");
+                return HoldTapDecision::Hold
+            }
+            self.hold_tap.flavor == HoldTapFlavor::HoldPreferred
+        } else {
+            // 只有在待定键之后按下的键松开才构成一次完整的轻击，之前按下的键松开不影响判定
+            let tapped = self.pressed_after.contains(&key_index);
+            tapped && self.hold_tap.flavor != HoldTapFlavor::TapPreferred
+        };
+        match is_hold {
+            true => HoldTapDecision::Hold,
+            false => HoldTapDecision::Pending,
+        }
+    }
+}
+
+/// 待定键按下时是否跳过判定，直接按轻击键处理
+///
+/// 1. quick tap：同一待定键轻击后`quick_tap_ms`内再次按下，`last_tap`为最近一次轻击的待定键及其松开时间
+/// 2. prior idle：距上一次按下按键(`last_press_at`)不足`prior_idle_ms`
+pub fn is_instant_tap(
+    hold_tap: &HoldTap,
+    key_index: usize,
+    last_tap: Option<(usize, Instant)>,
+    last_press_at: Instant,
+    now: Instant,
+) -> bool {
+    let within = |at: Instant, ms: u16| ms != 0 && now < at + Duration::from_millis(ms as u64);
+    let is_quick_tap = last_tap.is_some_and(|(index, at)| index == key_index && within(at, hold_tap.quick_tap_ms));
+    is_quick_tap || within(last_press_at, hold_tap.prior_idle_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::kbd::key::basic_key::*;
+    use HoldTapDecision::{Hold, Pending, Tap};
+
+    const HOLD_TAP_KEY: usize = 0;
+    const OTHER_KEY: u8 = 1;
+
+    const FLAVORS: [HoldTapFlavor; 3] = [
+        HoldTapFlavor::HoldPreferred,
+        HoldTapFlavor::Balanced,
+        HoldTapFlavor::TapPreferred,
+    ];
+
+    fn event(is_pressed: bool, key_index: u8, ms: u64) -> KeyEvent {
+        KeyEvent::new(is_pressed, key_index, Instant::from_millis(ms))
+    }
+
+    fn hold_tap(flavor: HoldTapFlavor) -> HoldTap {
+        HoldTap::new(LCtrl, F, 200).flavor(flavor)
+    }
+
+    fn start(flavor: HoldTapFlavor) -> HoldTapState {
+        HoldTapState::new(HOLD_TAP_KEY, hold_tap(flavor), Instant::from_millis(0), &mut Scheduler::default())
+    }
+
+    /// 在0ms按下待定键，按时间顺序执行事件脚本，时钟走到`end_ms`为止
+    ///
+    /// 与KbdCore一样，早于事件到时的超时任务先于该事件处理
+    fn run(flavor: HoldTapFlavor, script: &[KeyEvent], end_ms: u64) -> HoldTapDecision {
+        let mut scheduler = Scheduler::default();
+        let mut state = HoldTapState::new(HOLD_TAP_KEY, hold_tap(flavor), Instant::from_millis(0), &mut scheduler);
+        for event in script {
+            if scheduler.pop_expired(event.time) == Some(Task::HoldTapTimeout) {
+                return HoldTapDecision::Hold
+            }
+            let decision = state.on_event(event, &mut scheduler);
+            if decision != HoldTapDecision::Pending {
+                // 判定完成后不再超时
+                assert_eq!(scheduler.next_deadline(), None);
+                return decision
+            }
+        }
+        match scheduler.pop_expired(Instant::from_millis(end_ms)) {
+            Some(Task::HoldTapTimeout) => HoldTapDecision::Hold,
+            _ => HoldTapDecision::Pending,
+        }
+    }
+
+    /// 依次检查各判定策略的结果，`expected`与`FLAVORS`的顺序对应
+    fn check(script: &[KeyEvent], end_ms: u64, expected: [HoldTapDecision; 3]) {
+        for (flavor, expected) in FLAVORS.into_iter().zip(expected) {
+            assert_eq!(run(flavor, script, end_ms), expected, "{:?}", flavor);
+        }
+    }
+
+    #[test]
+    fn released_within_term_is_tap() {
+        check(&[event(false, HOLD_TAP_KEY as u8, 100)], 100, [Tap; 3]);
+    }
+
+    #[test]
+    fn held_past_term_is_hold() {
+        check(&[], 199, [Pending; 3]);
+        check(&[], 200, [Hold; 3]);
+        // 超时之后才松开，仍按超时判定
+        check(&[event(false, HOLD_TAP_KEY as u8, 250)], 250, [Hold; 3]);
+    }
+
+    #[test]
+    fn other_key_pressed() {
+        check(&[event(true, OTHER_KEY, 50)], 100, [Hold, Pending, Pending]);
+        // 其他键按住到超时
+        check(&[event(true, OTHER_KEY, 50), event(false, OTHER_KEY, 250)], 250, [Hold; 3]);
+    }
+
+    #[test]
+    fn other_key_tapped_inside() {
+        let script = [event(true, OTHER_KEY, 50), event(false, OTHER_KEY, 80)];
+        check(&script, 100, [Hold, Hold, Pending]);
+        // 之后松开待定键，仅超时判定的策略判定为轻击
+        let script = [event(true, OTHER_KEY, 50), event(false, OTHER_KEY, 80), event(false, HOLD_TAP_KEY as u8, 90)];
+        check(&script, 100, [Hold, Hold, Tap]);
+    }
+
+    #[test]
+    fn rolled_over_other_key() {
+        // 先松开待定键，再松开其他键
+        let script = [event(true, OTHER_KEY, 50), event(false, HOLD_TAP_KEY as u8, 80), event(false, OTHER_KEY, 90)];
+        check(&script, 100, [Hold, Tap, Tap]);
+    }
+
+    #[test]
+    fn release_of_key_pressed_before_is_ignored() {
+        let script = [event(false, OTHER_KEY, 10), event(false, HOLD_TAP_KEY as u8, 20)];
+        check(&script, 20, [Tap; 3]);
+    }
+
+    #[test]
+    fn flavor_aliases() {
+        assert_eq!(HoldTapFlavor::PermissiveHold, HoldTapFlavor::Balanced);
+        assert_eq!(HoldTapFlavor::HoldOnOtherKeyPress, HoldTapFlavor::HoldPreferred);
+    }
+
+    #[test]
+    fn too_many_presses_resolve_as_hold() {
+        let mut state = start(HoldTapFlavor::Balanced);
+        for key_index in 1..=HOLD_TAP_BUFFER_SIZE as u8 {
+            assert_eq!(state.on_event(&event(true, key_index, 10), &mut Scheduler::default()), Pending);
+        }
+        assert_eq!(state.on_event(&event(true, 100, 20), &mut Scheduler::default()), Hold);
+    }
+
+    #[test]
+    fn no_tapping_term_never_times_out() {
+        let hold_tap = HoldTap { tapping_term_ms: None, ..hold_tap(HoldTapFlavor::HoldPreferred) };
+        let mut scheduler = Scheduler::default();
+        HoldTapState::new(HOLD_TAP_KEY, hold_tap, Instant::from_millis(0), &mut scheduler);
+        assert_eq!(scheduler.next_deadline(), None);
+    }
+
+    #[test]
+    fn quick_tap_term() {
+        let hold_tap = hold_tap(HoldTapFlavor::Balanced).quick_tap(150);
+        let last_tap = Some((HOLD_TAP_KEY, Instant::from_millis(100)));
+        let instant_tap = |key_index: usize, ms: u64| {
+            is_instant_tap(&hold_tap, key_index, last_tap, Instant::MIN, Instant::from_millis(ms))
+        };
+        assert!(instant_tap(HOLD_TAP_KEY, 200));
+        assert!(!instant_tap(HOLD_TAP_KEY, 250));
+        // 只对同一待定键生效
+        assert!(!instant_tap(HOLD_TAP_KEY + 1, 200));
+        // 0表示关闭
+        let hold_tap = hold_tap.quick_tap(0);
+        assert!(!is_instant_tap(&hold_tap, HOLD_TAP_KEY, last_tap, Instant::MIN, Instant::from_millis(200)));
+    }
+
+    #[test]
+    fn require_prior_idle() {
+        let hold_tap = hold_tap(HoldTapFlavor::Balanced).prior_idle(100);
+        let last_press_at = Instant::from_millis(1000);
+        let instant_tap = |ms: u64| is_instant_tap(&hold_tap, HOLD_TAP_KEY, None, last_press_at, Instant::from_millis(ms));
+        assert!(instant_tap(1050));
+        assert!(!instant_tap(1100));
+        let hold_tap = hold_tap.prior_idle(0);
+        assert!(!is_instant_tap(&hold_tap, HOLD_TAP_KEY, None, last_press_at, Instant::from_millis(1050)));
+    }
+}
@@ -0,0 +1,51 @@
+// 主机LED状态
+// 主机通过输出报文下发NumLock、CapsLock等指示灯状态，USB收到后发布到`HOST_LEDS_WATCH`
+// 核心、指示灯等通过各自的接收端读取最新状态，状态变化时被唤醒
+
+#[cfg(not(test))]
+use super::channel::HOST_LEDS_WATCH;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostLeds(pub u8);
+
+#[allow(unused)]
+impl HostLeds {
+    /// 各LED在输出报文中的位，与HID LED用法页顺序一致
+    pub const NUM_LOCK: u8 = 1 << 0;
+    pub const CAPS_LOCK: u8 = 1 << 1;
+    pub const SCROLL_LOCK: u8 = 1 << 2;
+    pub const COMPOSE: u8 = 1 << 3;
+    pub const KANA: u8 = 1 << 4;
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn num_lock(&self) -> bool {
+        self.0 & Self::NUM_LOCK != 0
+    }
+
+    pub fn caps_lock(&self) -> bool {
+        self.0 & Self::CAPS_LOCK != 0
+    }
+
+    pub fn scroll_lock(&self) -> bool {
+        self.0 & Self::SCROLL_LOCK != 0
+    }
+}
+
+/// 发布主机下发的LED状态，状态未变化时不唤醒接收端
+#[cfg(not(test))]
+pub fn publish_host_leds(leds: u8) {
+    HOST_LEDS_WATCH.sender().send_if_modified(|cur| {
+        let is_modified = *cur != Some(HostLeds(leds));
+        *cur = Some(HostLeds(leds));
+        is_modified
+    });
+}
+
+/// 当前的主机LED状态，主机未下发过时全部视为熄灭
+#[cfg(not(test))]
+pub fn host_leds() -> HostLeds {
+    HOST_LEDS_WATCH.try_get().unwrap_or_default()
+}
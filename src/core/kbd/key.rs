@@ -6,6 +6,8 @@ pub enum KbdKey {
     /// 1.Shift、Ctrl、Alt、Gui(即Windows下的Win键)
     /// 2.切层键
     State(StateKey),
+    /// Consumer Page用法(16位)，用于`QwertyKey`中没有的媒体键
+    Consumer(u16),
 }
 
 /// 非Modifier Key，可直接转换为USB keycode
@@ -363,6 +365,77 @@ pub enum QwertyKey {
     MouseAccel2 = 0xDF,
 }
 
+impl QwertyKey {
+    /// USB键码对应的按键，未定义的键码返回`None`
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            // SAFETY: `QwertyKey`为`repr(u8)`，且以上范围内的值都有对应的枚举项
+            0x01..=0xC2 | 0xCD..=0xDF => Some(unsafe { core::mem::transmute::<u8, QwertyKey>(code) }),
+            _ => None,
+        }
+    }
+
+    /// ASCII字符对应的按键(US布局)，返回(按键, 是否需要按住Shift)
+    pub fn from_ascii(c: u8) -> Option<(Self, bool)> {
+        use QwertyKey::*;
+        const LETTERS: [QwertyKey; 26] = [
+            A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        ];
+        const DIGITS: [QwertyKey; 10] = [Kc0, Kc1, Kc2, Kc3, Kc4, Kc5, Kc6, Kc7, Kc8, Kc9];
+
+        let key = match c {
+            b'a'..=b'z' => (LETTERS[(c - b'a') as usize], false),
+            b'A'..=b'Z' => (LETTERS[(c - b'A') as usize], true),
+            b'0'..=b'9' => (DIGITS[(c - b'0') as usize], false),
+            b'!' => (Kc1, true),
+            b'@' => (Kc2, true),
+            b'#' => (Kc3, true),
+            b'$' => (Kc4, true),
+            b'%' => (Kc5, true),
+            b'^' => (Kc6, true),
+            b'&' => (Kc7, true),
+            b'*' => (Kc8, true),
+            b'(' => (Kc9, true),
+            b')' => (Kc0, true),
+            b'\n' => (Enter, false),
+            b'\t' => (Tab, false),
+            b' ' => (Space, false),
+            b'-' => (Minus, false),
+            b'_' => (Minus, true),
+            b'=' => (Equal, false),
+            b'+' => (Equal, true),
+            b'[' => (LeftBracket, false),
+            b'{' => (LeftBracket, true),
+            b']' => (RightBracket, false),
+            b'}' => (RightBracket, true),
+            b'\\' => (Backslash, false),
+            b'|' => (Backslash, true),
+            b';' => (Semicolon, false),
+            b':' => (Semicolon, true),
+            b'\'' => (Quote, false),
+            b'"' => (Quote, true),
+            b'`' => (Grave, false),
+            b'~' => (Grave, true),
+            b',' => (Comma, false),
+            b'<' => (Comma, true),
+            b'.' => (Dot, false),
+            b'>' => (Dot, true),
+            b'/' => (Slash, false),
+            b'?' => (Slash, true),
+            _ => return None,
+        };
+        Some(key)
+    }
+}
+
+impl KbdKey {
+    /// USB键码对应的普通键或修饰键
+    pub fn from_code(code: u8) -> Option<Self> {
+        QwertyKey::from_code(code).map(KbdKey::Normal)
+            .or_else(|| ModifierKey::from_code(code).map(Into::into))
+    }
+}
+
 impl From<QwertyKey> for KbdKey {
     fn from(value: QwertyKey) -> Self {
         KbdKey::Normal(value)
@@ -405,6 +478,20 @@ pub enum ModifierKey {
     RGui = 0xE7,
 }
 
+impl ModifierKey {
+    /// USB键码对应的修饰键
+    pub fn from_code(code: u8) -> Option<Self> {
+        use ModifierKey::*;
+        const KEYS: [ModifierKey; 8] = [LCtrl, LShift, LAlt, LGui, RCtrl, RShift, RAlt, RGui];
+        KEYS.get(code.checked_sub(LCtrl as u8)? as usize).copied()
+    }
+
+    /// 在报文modifier字节中对应的位
+    pub const fn bit(self) -> u8 {
+        1 << (self as u8 & 0x0F)
+    }
+}
+
 impl From<ModifierKey> for StateKey {
     fn from(value: ModifierKey) -> Self {
         StateKey::Modifier(value)
@@ -414,8 +501,24 @@ impl From<ModifierKey> for StateKey {
 #[allow(unused)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum LayerKey {
+    /// 按住时激活指定层
     LayerOn(u8),
+    /// 按下时开关指定层
     LayerSwitch(u8),
+    /// 按下时仅激活指定层，关闭其他层
+    LayerTo(u8),
+    /// 按下时将指定层设为默认层
+    DefaultLayer(u8),
+}
+
+impl LayerKey {
+    /// 操作的层
+    pub fn layer(self) -> u8 {
+        match self {
+            LayerKey::LayerOn(layer) | LayerKey::LayerSwitch(layer)
+                | LayerKey::LayerTo(layer) | LayerKey::DefaultLayer(layer) => layer,
+        }
+    }
 }
 
 impl From<LayerKey> for StateKey {
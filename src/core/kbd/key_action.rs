@@ -1,4 +1,6 @@
-use super::key::{KbdKey, QwertyKey, StateKey, LayerKey};
+use super::key::{KbdKey, QwertyKey, StateKey, LayerKey, ModifierKey};
+use crate::core::unicode::UnicodeMode;
+use crate::kbd_cfg::core::{COMBO_MAX_KEYS, COMBO_TIMEOUT_MS, TAP_DANCE_MAX_TAPS};
 
 #[allow(unused)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -7,6 +9,28 @@ pub enum KeyAction {
     CK(KbdKey),
     /// 待定键
     UK(UncertKey),
+    /// 轻击舞(Tap Dance)，参数为`KeyMapTables::tap_dances`的索引
+    TD(u8),
+    /// One-shot状态键，轻击后仅作用于下一个普通键
+    OS(StateKey),
+    /// 宏，参数为`KeyMapTables::macros`的索引
+    Macro(u8),
+    /// 动态宏，参数为VIA宏缓冲区中的宏序号，见`macros::DynamicMacros`
+    DynMacro(u8),
+    /// 通过系统输入法输入Unicode字符
+    Unicode(char),
+    /// 切换Unicode输入方式
+    UnicodeMode(UnicodeMode),
+    /// 开关自动Shift
+    AutoShiftToggle,
+    /// Leader键，开始捕获按键序列
+    Leader,
+    /// 开关Caps Word
+    CapsWord,
+    /// 以当时的修饰键重复上一个普通键
+    Repeat,
+    /// 发送上一个普通键的反向键，见`KeyMapTables::alt_repeats`
+    AltRepeat,
     /// 布局传递用
     TS,
     /// 无动作，注意，KbdKey中禁用了None键，要想表示None需使用该枚举
@@ -30,6 +54,220 @@ pub enum UncertKey {
     SK(StateKey, QwertyKey),
     /// SK with tap threshold
     HK(StateKey, QwertyKey, u16),
+    /// 可配置判定策略的待定键，参数为`KeyMapTables::hold_taps`的索引
+    HT(u8),
+}
+
+/// 待定键的判定策略，决定按住期间其他键的事件如何影响判定
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HoldTapFlavor {
+    /// 其他键按下，或在待定键之后按下的键松开时立即判定为按住，`sk`/`hk`使用该策略
+    HoldPreferred,
+    /// 在待定键之后按下的其他键完成一次完整的按下+松开时判定为按住
+    Balanced,
+    /// 仅超时判定为按住
+    TapPreferred,
+}
+
+/// ZMK/QMK中的同义策略名
+#[allow(unused, non_upper_case_globals)]
+impl HoldTapFlavor {
+    /// QMK的permissive hold，即`Balanced`(之前按下的键松开本就不影响判定)
+    pub const PermissiveHold: Self = Self::Balanced;
+    /// QMK的hold on other key press，即`HoldPreferred`(其他键按下时已判定，松开无需再判)
+    pub const HoldOnOtherKeyPress: Self = Self::HoldPreferred;
+}
+
+/// 待定键配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HoldTap {
+    pub state_key: StateKey,
+    pub qwerty_key: QwertyKey,
+    /// 按住超过该时间(ms)判定为按住，`None`表示不超时
+    pub tapping_term_ms: Option<u16>,
+    pub flavor: HoldTapFlavor,
+    /// 轻击后在该时间(ms)内再次按下，直接按住轻击键(用于连发)，0表示关闭
+    pub quick_tap_ms: u16,
+    /// 距上一次按下其他键不足该时间(ms)时直接判定为轻击(用于快速打字)，0表示关闭
+    pub prior_idle_ms: u16,
+}
+
+#[allow(unused)]
+impl HoldTap {
+    pub fn new<SK: Into<StateKey>, QK: Into<QwertyKey>>(state_key: SK, qwerty_key: QK, tapping_term_ms: u16) -> Self {
+        Self {
+            state_key: state_key.into(),
+            qwerty_key: qwerty_key.into(),
+            tapping_term_ms: Some(tapping_term_ms),
+            flavor: HoldTapFlavor::HoldPreferred,
+            quick_tap_ms: 0,
+            prior_idle_ms: 0,
+        }
+    }
+
+    pub fn flavor(mut self, flavor: HoldTapFlavor) -> Self {
+        self.flavor = flavor;
+        self
+    }
+
+    pub fn quick_tap(mut self, quick_tap_ms: u16) -> Self {
+        self.quick_tap_ms = quick_tap_ms;
+        self
+    }
+
+    pub fn prior_idle(mut self, prior_idle_ms: u16) -> Self {
+        self.prior_idle_ms = prior_idle_ms;
+        self
+    }
+}
+
+/// 轻击舞配置，根据连击次数(及最后一次是否按住)决定触发的键
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapDance {
+    /// 连击判定窗口(ms)，超过该时间没有再次按下/松开则结束连击
+    pub tapping_term_ms: u16,
+    /// 第n次连击对应的(轻击键, 按住键)，下标从0开始(即第1次连击)
+    pub steps: [(Option<KbdKey>, Option<KbdKey>); TAP_DANCE_MAX_TAPS],
+}
+
+#[allow(unused)]
+impl TapDance {
+    pub const fn new(tapping_term_ms: u16) -> Self {
+        Self { tapping_term_ms, steps: [(None, None); TAP_DANCE_MAX_TAPS] }
+    }
+
+    /// 设置连击`count`次(从1开始)后松开时触发的键
+    pub fn tap<K: Into<KbdKey>>(mut self, count: usize, key: K) -> Self {
+        self.steps[count-1].0 = Some(key.into());
+        self
+    }
+
+    /// 设置连击`count`次(从1开始)后按住时触发的键
+    pub fn hold<K: Into<KbdKey>>(mut self, count: usize, key: K) -> Self {
+        self.steps[count-1].1 = Some(key.into());
+        self
+    }
+
+    /// 配置的最大连击次数，达到该次数后无需等待即可判定
+    pub fn max_taps(&self) -> usize {
+        self.steps.iter()
+            .rposition(|(tap, hold)| tap.is_some() || hold.is_some())
+            .map_or(0, |idx| idx+1)
+    }
+}
+
+/// 组合键配置，在判定窗口内按下全部成员键时触发`action`，代替各成员键原本的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Combo {
+    /// 成员键(物理按键索引)，仅前`key_num`个有效
+    keys: [u8; COMBO_MAX_KEYS],
+    key_num: usize,
+    /// 触发的动作
+    pub action: KeyAction,
+    /// 判定窗口(ms)，从按下第一个成员键开始计时
+    pub timeout_ms: u16,
+    /// 仅在指定层激活时生效，`None`表示所有层都生效
+    pub layer: Option<u8>,
+}
+
+#[allow(unused)]
+impl Combo {
+    pub fn new(keys: &[u8], action: KeyAction) -> Self {
+        assert!(keys.len() >= 2 && keys.len() <= COMBO_MAX_KEYS);
+        // 组合键不在布局中，没有下层可以透明到
+        assert!(action != KeyAction::TS, "combo action cannot be transparent");
+        let mut combo_keys = [0; COMBO_MAX_KEYS];
+        combo_keys[..keys.len()].copy_from_slice(keys);
+        Self { keys: combo_keys, key_num: keys.len(), action, timeout_ms: COMBO_TIMEOUT_MS, layer: None }
+    }
+
+    pub fn timeout(mut self, timeout_ms: u16) -> Self {
+        self.timeout_ms = timeout_ms;
+        self
+    }
+
+    pub fn layer(mut self, layer: u8) -> Self {
+        self.layer = Some(layer);
+        self
+    }
+
+    pub fn keys(&self) -> &[u8] {
+        &self.keys[..self.key_num]
+    }
+}
+
+/// 宏的单个步骤
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroStep {
+    /// 按下并松开
+    Tap(KbdKey),
+    /// 按下
+    Press(KbdKey),
+    /// 松开
+    Release(KbdKey),
+    /// 等待(ms)
+    Delay(u16),
+    /// 输入ASCII字符串(US布局)
+    Text(&'static str),
+}
+
+/// Leader键序列，依次按下`keys`后触发`action`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeaderSequence {
+    pub keys: &'static [QwertyKey],
+    pub action: KeyAction,
+}
+
+#[allow(unused)]
+impl LeaderSequence {
+    pub const fn new(keys: &'static [QwertyKey], action: KeyAction) -> Self {
+        Self { keys, action }
+    }
+}
+
+/// 按键覆盖，按住`trigger_mods`中的修饰键(不区分左右)时按下`trigger`，
+/// 改为以`replacement_mods`发送`replacement`，松开后恢复原修饰键
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyOverride {
+    pub trigger_mods: u8,
+    pub trigger: QwertyKey,
+    pub replacement: QwertyKey,
+    pub replacement_mods: u8,
+}
+
+#[allow(unused)]
+impl KeyOverride {
+    pub const fn new(trigger_mods: u8, trigger: QwertyKey, replacement: QwertyKey, replacement_mods: u8) -> Self {
+        Self { trigger_mods, trigger, replacement, replacement_mods }
+    }
+}
+
+/// 反向重复键对，`key`与`alt`互为反向键，修饰键保持不变(如`9`/`0`对应`(`/`)`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AltRepeat {
+    pub key: QwertyKey,
+    pub alt: QwertyKey,
+}
+
+#[allow(unused)]
+impl AltRepeat {
+    pub const fn new(key: QwertyKey, alt: QwertyKey) -> Self {
+        Self { key, alt }
+    }
+}
+
+/// 修饰键集合对应的modifier字节
+#[allow(unused)]
+pub const fn mods(keys: &[ModifierKey]) -> u8 {
+    let mut bits = 0;
+    let mut idx = 0;
+    while idx < keys.len() {
+        bits |= keys[idx].bit();
+        idx += 1;
+    }
+    bits
 }
 
 /// 普通按键逻辑，按下即刻触发
@@ -58,6 +296,98 @@ pub fn hk<SK: Into<StateKey>, QK: Into<QwertyKey>>(state_key: SK, qwerty_key: QK
     KeyAction::UK(UncertKey::HK(state_key.into(), qwerty_key.into(), tap_threshold_ms))
 }
 
+/// 轻击舞
+///
+/// 按连击次数触发不同的键，每个连击次数可以分别配置轻击和按住的键，
+/// 具体配置见`KeyMapTables::tap_dances[index]`
+#[allow(unused)]
+pub fn td(index: u8) -> KeyAction {
+    KeyAction::TD(index)
+}
+
+/// One-shot修饰键
+///
+/// 1. 按住时与普通修饰键相同
+/// 2. 轻击后修饰键保持激活，按下下一个普通键后自动撤销，超时也会撤销
+/// 3. 轻击后再次按下则锁定，直到下次按下
+#[allow(unused)]
+pub fn osm(modifier_key: ModifierKey) -> KeyAction {
+    KeyAction::OS(modifier_key.into())
+}
+
+/// One-shot层，逻辑同`osm`
+#[allow(unused)]
+pub fn osl(layer: u8) -> KeyAction {
+    KeyAction::OS(LayerKey::LayerOn(layer).into())
+}
+
+/// 宏，按下时依次执行`KeyMapTables::macros[index]`中的步骤
+#[allow(unused)]
+pub fn mc(index: u8) -> KeyAction {
+    KeyAction::Macro(index)
+}
+
+/// 动态宏，按下时播放VIA宏缓冲区中的第`index`个宏
+#[allow(unused)]
+pub fn dmc(index: u8) -> KeyAction {
+    KeyAction::DynMacro(index)
+}
+
+/// 输入Unicode字符，输入方式由`ucm`切换
+#[allow(unused)]
+pub fn uc(c: char) -> KeyAction {
+    KeyAction::Unicode(c)
+}
+
+/// 切换Unicode输入方式
+#[allow(unused)]
+pub fn ucm(mode: UnicodeMode) -> KeyAction {
+    KeyAction::UnicodeMode(mode)
+}
+
+/// 开关自动Shift
+#[allow(unused)]
+pub fn ast() -> KeyAction {
+    KeyAction::AutoShiftToggle
+}
+
+/// Leader键，按下后依次按下的按键与`KeyMapTables::leader_sequences`匹配，
+/// 匹配成功时触发对应的动作，捕获的按键不会发送给主机
+#[allow(unused)]
+pub fn lead() -> KeyAction {
+    KeyAction::Leader
+}
+
+/// 开关Caps Word，激活期间字母自动大写、`-`变为`_`，输入其他符号或空格时自动结束
+#[allow(unused)]
+pub fn cw() -> KeyAction {
+    KeyAction::CapsWord
+}
+
+/// 可配置判定策略的待定键，具体配置见`KeyMapTables::hold_taps[index]`
+#[allow(unused)]
+pub fn ht(index: u8) -> KeyAction {
+    KeyAction::UK(UncertKey::HT(index))
+}
+
+/// Consumer Page按键，可发送任意16位用法，如`0x006F`(亮度+)
+#[allow(unused)]
+pub fn cc(usage: u16) -> KeyAction {
+    KeyAction::CK(KbdKey::Consumer(usage))
+}
+
+/// 重复上一个普通键
+#[allow(unused)]
+pub fn rep() -> KeyAction {
+    KeyAction::Repeat
+}
+
+/// 发送上一个普通键的反向键
+#[allow(unused)]
+pub fn arep() -> KeyAction {
+    KeyAction::AltRepeat
+}
+
 /// 按住时启用指定层
 #[allow(unused)]
 pub fn lo(layer: u8) -> KeyAction {
@@ -70,6 +400,18 @@ pub fn ls(layer: u8) -> KeyAction {
     ck(LayerKey::LayerSwitch(layer))
 }
 
+/// 切换到指定层，关闭其他层(默认层除外)
+#[allow(unused)]
+pub fn lm(layer: u8) -> KeyAction {
+    ck(LayerKey::LayerTo(layer))
+}
+
+/// 设置默认层
+#[allow(unused)]
+pub fn ld(layer: u8) -> KeyAction {
+    ck(LayerKey::DefaultLayer(layer))
+}
+
 
 // TODO(L): 用宏代替，方便写布局
 #[allow(unused)]
@@ -1,12 +1,16 @@
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+use embassy_time::Instant;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct KeyEvent {
     pub is_pressed: bool,
     /// 仅支持256个按键(不会有人用超过256键吧，乐)
     pub key_index: u8,
+    /// 扫描到(消抖后)按键变化的时间，时间相关的判定都以此为准
+    pub time: Instant,
 }
 
 impl KeyEvent {
-    pub fn new(is_pressed: bool, key_index: u8) -> Self {
-        Self { is_pressed, key_index: key_index.into() }
+    pub fn new(is_pressed: bool, key_index: u8, time: Instant) -> Self {
+        Self { is_pressed, key_index, time }
     }
 }
\ No newline at end of file
@@ -2,4 +2,5 @@ pub mod key;
 pub mod key_event;
 #[macro_use]
 pub mod key_action;
+#[cfg(not(test))]
 pub mod debounce;
\ No newline at end of file
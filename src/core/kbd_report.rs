@@ -0,0 +1,55 @@
+// 键盘报文
+// 报告协议(Report Protocol)下使用位图报文实现全键无冲(NKRO)
+// 主机通过SET_PROTOCOL切换到启动协议(Boot Protocol)时，退回到BIOS能识别的固定8字节6键报文
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// 位图覆盖的键码范围为`0x00..=0xDF`，修饰键单独占一个字节
+pub const NKRO_BITMAP_SIZE: usize = 0xE0 / 8;
+
+/// 最大报文长度(NKRO报文)
+#[cfg(not(test))]
+pub const KBD_REPORT_SIZE: usize = 1 + NKRO_BITMAP_SIZE;
+
+/// 启动协议报文的按键数
+pub const BOOT_KEY_NUM: usize = 6;
+
+/// 主机是否切换到了启动协议，USB复位后恢复为报告协议
+static BOOT_PROTOCOL: AtomicBool = AtomicBool::new(false);
+
+#[cfg(not(test))]
+pub fn set_boot_protocol(is_boot: bool) {
+    BOOT_PROTOCOL.store(is_boot, Ordering::Release);
+}
+
+pub fn is_boot_protocol() -> bool {
+    BOOT_PROTOCOL.load(Ordering::Acquire)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KbdReport {
+    /// 启动协议报文：modifier、保留字节、6个键码
+    Boot { modifier: u8, keycodes: [u8; BOOT_KEY_NUM] },
+    /// 全键无冲报文：modifier、按键位图
+    Nkro { modifier: u8, bitmap: [u8; NKRO_BITMAP_SIZE] },
+}
+
+impl KbdReport {
+    /// 序列化为HID报文，返回报文长度
+    #[cfg(not(test))]
+    pub fn serialize(&self, buf: &mut [u8; KBD_REPORT_SIZE]) -> usize {
+        match self {
+            KbdReport::Boot { modifier, keycodes } => {
+                buf[0] = *modifier;
+                buf[1] = 0;
+                buf[2..2 + BOOT_KEY_NUM].copy_from_slice(keycodes);
+                2 + BOOT_KEY_NUM
+            },
+            KbdReport::Nkro { modifier, bitmap } => {
+                buf[0] = *modifier;
+                buf[1..].copy_from_slice(bitmap);
+                KBD_REPORT_SIZE
+            },
+        }
+    }
+}
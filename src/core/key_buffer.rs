@@ -1,23 +1,71 @@
-use usbd_hid::descriptor::KeyboardReport;
+use super::kbd_report::{KbdReport, BOOT_KEY_NUM, NKRO_BITMAP_SIZE};
+
+/// 启动协议下同时按下超过6个键时，按规范所有键码位置填ErrorRollOver
+const ERROR_ROLL_OVER: u8 = 0x01;
 
-#[derive(Default)]
 pub struct KeyBuffer {
     modifier: u8,
-    keycodes: [u8; 7],
-    cnt: usize,
+    /// 临时替换报文中的modifier，用于按键覆盖(Key Override)
+    modifier_override: Option<u8>,
+    /// 播放Unicode输入序列时序列自身按下的修饰键，期间代替其他modifier发送，避免用户按住的修饰键混入序列
+    macro_modifier: Option<u8>,
+    /// 按下的键码位图，第n位对应键码n
+    bitmap: [u8; NKRO_BITMAP_SIZE],
+    /// 各键码的按下次数，同一键码可能被多个来源按下(如重复键、多个按键映射到同一键码)，全部松开后才从位图中清除
+    press_counts: [u8; NKRO_BITMAP_SIZE * 8],
+}
+
+impl Default for KeyBuffer {
+    fn default() -> Self {
+        Self {
+            modifier: 0,
+            modifier_override: None,
+            macro_modifier: None,
+            bitmap: [0; NKRO_BITMAP_SIZE],
+            press_counts: [0; NKRO_BITMAP_SIZE * 8],
+        }
+    }
 }
 
 impl KeyBuffer {
-    // TODO(VL): 改成直接生成最终报文?
-    pub fn get_cur_report(&self) -> KeyboardReport {
-        KeyboardReport {
-            modifier: self.modifier,
-            keycodes: [
-                self.keycodes[0], self.keycodes[1], self.keycodes[2],
-                self.keycodes[3], self.keycodes[4], self.keycodes[5],
-            ],
-            ..KeyboardReport::default()
+    /// 生成当前报文，`is_boot`为`true`时生成启动协议报文
+    pub fn get_cur_report(&self, is_boot: bool) -> KbdReport {
+        let modifier = self.report_modifier();
+        if !is_boot {
+            return KbdReport::Nkro { modifier, bitmap: self.bitmap }
         }
+
+        let mut keycodes = [0; BOOT_KEY_NUM];
+        let mut keys = (0..NKRO_BITMAP_SIZE * 8).filter(|&code| self.is_pressed(code as u8));
+        for (slot, code) in keycodes.iter_mut().zip(keys.by_ref()) {
+            *slot = code as u8;
+        }
+        if keys.next().is_some() {
+            keycodes = [ERROR_ROLL_OVER; BOOT_KEY_NUM];
+        }
+        KbdReport::Boot { modifier, keycodes }
+    }
+
+    pub fn modifier(&self) -> u8 {
+        self.modifier
+    }
+
+    /// 报文中实际发送的modifier
+    pub fn report_modifier(&self) -> u8 {
+        self.macro_modifier.or(self.modifier_override).unwrap_or(self.modifier)
+    }
+
+    pub fn set_modifier_override(&mut self, modifier: Option<u8>) {
+        self.modifier_override = modifier;
+    }
+
+    pub fn macro_modifier(&self) -> Option<u8> {
+        self.macro_modifier
+    }
+
+    /// `Some`时报文只使用其中的modifier，`None`时恢复
+    pub fn set_macro_modifier(&mut self, modifier: Option<u8>) {
+        self.macro_modifier = modifier;
     }
 
     pub fn set_modifier(&mut self, key_code: u8) {
@@ -29,24 +77,71 @@ impl KeyBuffer {
     }
 
     pub fn presse_key(&mut self, key_code: u8) {
-        if self.cnt == 6 {
-            defmt::warn!("key_buffer full, can't cache key `{}`", key_code);
+        if key_code as usize >= NKRO_BITMAP_SIZE * 8 {
+            defmt::warn!("Key `{}` out of report range", key_code);
             return
         }
 
-        self.keycodes[self.cnt] = key_code;
-        self.cnt += 1;
+        let count = &mut self.press_counts[key_code as usize];
+        *count = count.saturating_add(1);
+        self.bitmap[key_code as usize / 8] |= 1 << (key_code % 8);
     }
 
     pub fn release_key(&mut self, key_code: u8) {
-        if let Some(index) = self.keycodes.iter().position(|&v| v==key_code) {
-            for idx in index..(self.cnt-1) {
-                self.keycodes[idx] = self.keycodes[idx+1];
-            }
-            self.keycodes[self.cnt-1] = 0;
-            self.cnt -= 1;
-        } else {
-            defmt::error!("Release a uncached key `{}` in key_buffer", key_code)
+        let Some(count) = self.press_counts.get_mut(key_code as usize).filter(|count| **count > 0) else {
+            defmt::error!("Release a uncached key `{}` in key_buffer", key_code);
+            return
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.bitmap[key_code as usize / 8] &= !(1 << (key_code % 8));
+        }
+    }
+
+    fn is_pressed(&self, key_code: u8) -> bool {
+        self.bitmap.get(key_code as usize / 8).is_some_and(|byte| byte & (1 << (key_code % 8)) != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: u8 = 0x04;
+    const B: u8 = 0x05;
+
+    fn boot_keycodes(key_buffer: &KeyBuffer) -> [u8; BOOT_KEY_NUM] {
+        match key_buffer.get_cur_report(true) {
+            KbdReport::Boot { keycodes, .. } => keycodes,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn key_pressed_twice_needs_two_releases() {
+        let mut key_buffer = KeyBuffer::default();
+        // 按住原键时按下重复键
+        key_buffer.presse_key(A);
+        key_buffer.presse_key(A);
+        key_buffer.release_key(A);
+        assert!(key_buffer.is_pressed(A));
+        key_buffer.release_key(A);
+        assert!(!key_buffer.is_pressed(A));
+        // 多余的松开不会影响计数
+        key_buffer.release_key(A);
+        key_buffer.presse_key(A);
+        assert!(key_buffer.is_pressed(A));
+    }
+
+    #[test]
+    fn boot_report_rolls_over() {
+        let mut key_buffer = KeyBuffer::default();
+        key_buffer.presse_key(A);
+        key_buffer.presse_key(B);
+        assert_eq!(boot_keycodes(&key_buffer), [A, B, 0, 0, 0, 0]);
+        for code in B + 1..B + 6 {
+            key_buffer.presse_key(code);
         }
+        assert_eq!(boot_keycodes(&key_buffer), [ERROR_ROLL_OVER; BOOT_KEY_NUM]);
     }
-}
\ No newline at end of file
+}
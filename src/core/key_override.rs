@@ -0,0 +1,23 @@
+// 按键覆盖(Key Override)
+// 按住指定修饰键时按下触发键，改为发送替换键和替换后的修饰键
+
+use super::kbd::key::QwertyKey;
+use super::kbd::key_action::KeyOverride;
+
+/// 将左右修饰键合并到低4位
+fn fold_mods(modifier: u8) -> u8 {
+    (modifier | (modifier >> 4)) & 0x0F
+}
+
+/// 查找匹配的覆盖规则，返回(替换键, 替换后的modifier字节)
+pub fn find_override(overrides: &[KeyOverride], qwerty_key: QwertyKey, modifier: u8) -> Option<(QwertyKey, u8)> {
+    let active = fold_mods(modifier);
+    overrides.iter()
+        .find(|o| o.trigger == qwerty_key && o.trigger_mods != 0 && active & fold_mods(o.trigger_mods) == fold_mods(o.trigger_mods))
+        .map(|o| {
+            // 去掉触发用的修饰键(左右两侧)，加上替换的修饰键
+            let trigger = fold_mods(o.trigger_mods);
+            let cleared = modifier & !(trigger | (trigger << 4));
+            (o.replacement, cleared | o.replacement_mods)
+        })
+}
@@ -0,0 +1,194 @@
+// 层激活状态
+// 使用位图记录激活的层，默认层(base layer)单独记录且始终视为激活
+// 条件层由规则根据其他层和主机LED的状态推导，每次层状态或LED状态变化后重新计算
+// 超出位图范围(>=32)的层会被忽略，布局层数的检查由调用方负责
+
+use super::host_leds::HostLeds;
+
+/// 条件层规则，`required`中的层全部激活且`excluded`中的层全部未激活时激活`layer`
+///
+/// 也可以要求主机LED的状态，如NumLock亮起时激活小键盘层
+///
+/// 同一个`layer`可以配置多条规则，任一规则满足即激活，没有任何条件的规则始终满足
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayerRule {
+    pub layer: u8,
+    pub required: u32,
+    pub excluded: u32,
+    /// 要求亮起/熄灭的主机LED，见`HostLeds`
+    pub leds_on: u8,
+    pub leds_off: u8,
+}
+
+#[allow(unused)]
+impl LayerRule {
+    pub const fn new(layer: u8) -> Self {
+        assert!(layer < 32, "layer out of range");
+        Self { layer, required: 0, excluded: 0, leds_on: 0, leds_off: 0 }
+    }
+
+    /// 三层联动(tri-layer)，`a`、`b`同时激活时激活`c`
+    pub const fn tri_layer(a: u8, b: u8, c: u8) -> Self {
+        Self::new(c).when_on(a).when_on(b)
+    }
+
+    pub const fn when_on(mut self, layer: u8) -> Self {
+        assert!(layer < 32, "layer out of range");
+        self.required |= 1 << layer;
+        self
+    }
+
+    pub const fn when_off(mut self, layer: u8) -> Self {
+        assert!(layer < 32, "layer out of range");
+        self.excluded |= 1 << layer;
+        self
+    }
+
+    /// 主机LED亮起时满足，`led`为`HostLeds::NUM_LOCK`等
+    pub const fn when_led_on(mut self, led: u8) -> Self {
+        self.leds_on |= led;
+        self
+    }
+
+    pub const fn when_led_off(mut self, led: u8) -> Self {
+        self.leds_off |= led;
+        self
+    }
+
+    fn is_satisfied(&self, active: u32, leds: u8) -> bool {
+        active & self.required == self.required && active & self.excluded == 0
+            && leds & self.leds_on == self.leds_on && leds & self.leds_off == 0
+    }
+}
+
+/// 层激活状态，最多支持32层
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LayerState {
+    /// 位图，第n位表示第n层是否激活
+    state: u32,
+    /// 条件层规则推导出的层
+    derived: u32,
+    /// 默认层
+    default_layer: u8,
+}
+
+#[allow(unused)]
+impl LayerState {
+    /// 指定层是否激活
+    pub fn is_active(&self, layer: u8) -> bool {
+        self.bits() & Self::bit(layer) != 0
+    }
+
+    /// 激活指定层
+    pub fn layer_on(&mut self, layer: u8) {
+        self.state |= Self::bit(layer);
+    }
+
+    /// 关闭指定层
+    pub fn layer_off(&mut self, layer: u8) {
+        self.state &= !Self::bit(layer);
+    }
+
+    /// 开关指定层
+    pub fn layer_toggle(&mut self, layer: u8) {
+        self.state ^= Self::bit(layer);
+    }
+
+    /// 仅激活指定层，关闭其他层(默认层除外)
+    pub fn layer_move(&mut self, layer: u8) {
+        if Self::bit(layer) != 0 {
+            self.state = Self::bit(layer);
+        }
+    }
+
+    /// 设置默认层
+    pub fn set_default_layer(&mut self, layer: u8) {
+        if Self::bit(layer) != 0 {
+            self.default_layer = layer;
+        }
+    }
+
+    pub fn default_layer(&self) -> u8 {
+        self.default_layer
+    }
+
+    /// 当前激活的最高层
+    pub fn highest_layer(&self) -> u8 {
+        (31 - self.bits().leading_zeros()) as u8
+    }
+
+    /// 激活层位图(包含默认层和条件层)
+    pub fn bits(&self) -> u32 {
+        self.state | self.derived | Self::bit(self.default_layer)
+    }
+
+    /// 层在位图中对应的位，超出范围时为0
+    fn bit(layer: u8) -> u32 {
+        let bit = 1u32.checked_shl(layer as u32).unwrap_or(0);
+        if bit == 0 {
+            defmt::warn!("Layer `{}` out of range, ignored", layer);
+        }
+        bit
+    }
+
+    /// 根据规则重新计算条件层，规则之间可以相互依赖，迭代直到结果稳定
+    pub fn apply_rules(&mut self, rules: &[LayerRule], leds: HostLeds) {
+        self.derived = 0;
+        for _ in 0..32 {
+            let active = self.bits();
+            let derived = rules.iter()
+                .filter(|rule| rule.is_satisfied(active, leds.bits()))
+                .fold(0, |bits, rule| bits | Self::bit(rule.layer));
+            if derived == self.derived {
+                return
+            }
+            self.derived = derived;
+        }
+        defmt::warn!("Layer rules do not converge");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tri_layer() {
+        let rules = [LayerRule::tri_layer(1, 2, 3)];
+        let mut layer_state = LayerState::default();
+        layer_state.layer_on(1);
+        layer_state.apply_rules(&rules, HostLeds::default());
+        assert!(!layer_state.is_active(3));
+        layer_state.layer_on(2);
+        layer_state.apply_rules(&rules, HostLeds::default());
+        assert!(layer_state.is_active(3));
+        assert_eq!(layer_state.highest_layer(), 3);
+    }
+
+    #[test]
+    fn rule_with_only_excluded_layers() {
+        // 层1关闭时激活层2
+        let rules = [LayerRule::new(2).when_off(1)];
+        let mut layer_state = LayerState::default();
+        layer_state.apply_rules(&rules, HostLeds::default());
+        assert!(layer_state.is_active(2));
+        layer_state.layer_toggle(1);
+        layer_state.apply_rules(&rules, HostLeds::default());
+        assert!(!layer_state.is_active(2));
+    }
+
+    #[test]
+    fn out_of_range_layer_is_ignored() {
+        let mut layer_state = LayerState::default();
+        layer_state.layer_on(1);
+        for layer in [32, 40, u8::MAX] {
+            layer_state.layer_on(layer);
+            layer_state.layer_toggle(layer);
+            layer_state.layer_move(layer);
+            layer_state.set_default_layer(layer);
+            assert!(!layer_state.is_active(layer));
+        }
+        assert_eq!(layer_state.bits(), 0b11);
+        assert_eq!(layer_state.default_layer(), 0);
+    }
+}
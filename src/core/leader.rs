@@ -0,0 +1,88 @@
+// Leader键序列判定逻辑
+// 按下Leader键后捕获后续按键，与序列表逐个匹配，捕获的按键不会发送给主机
+// 与IO无关，匹配成功后由调用方触发对应的动作
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use super::kbd::key::QwertyKey;
+use super::kbd::key_action::{KeyAction, LeaderSequence};
+use super::scheduler::{Scheduler, Task};
+use crate::kbd_cfg::core::{LEADER_MAX_KEYS, LEADER_TIMEOUT_MS};
+
+/// 判定结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaderDecision {
+    /// 继续捕获
+    Pending,
+    /// 匹配成功
+    Matched(KeyAction),
+    /// 没有匹配的序列，结束捕获
+    Cancelled,
+}
+
+pub struct Leader {
+    /// 最后捕获的按键索引(初始为Leader键)，用于触发匹配的动作
+    pub key_index: usize,
+    /// 已捕获的按键
+    keys: Vec<QwertyKey, LEADER_MAX_KEYS>,
+    /// 捕获截止时间，每捕获一个按键重新计时
+    deadline: Instant,
+}
+
+impl Leader {
+    pub fn new(key_index: usize, now: Instant, scheduler: &mut Scheduler) -> Self {
+        let deadline = now + Duration::from_millis(LEADER_TIMEOUT_MS);
+        scheduler.schedule(deadline, Task::LeaderTimeout);
+        Self { key_index, keys: Vec::new(), deadline }
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// 捕获一个按键
+    ///
+    /// 恰好匹配且不存在更长的候选序列时立即触发，否则等待后续按键或超时
+    ///
+    /// 捕获结束时取消超时任务
+    pub fn on_key(
+        &mut self,
+        sequences: &[LeaderSequence],
+        key_index: usize,
+        key: QwertyKey,
+        now: Instant,
+        scheduler: &mut Scheduler,
+    ) -> LeaderDecision {
+        let decision = self.capture(sequences, key_index, key, now);
+        match decision {
+            LeaderDecision::Pending => scheduler.schedule(self.deadline, Task::LeaderTimeout),
+            LeaderDecision::Matched(_) | LeaderDecision::Cancelled => scheduler.cancel(Task::LeaderTimeout),
+        }
+        decision
+    }
+
+    fn capture(&mut self, sequences: &[LeaderSequence], key_index: usize, key: QwertyKey, now: Instant) -> LeaderDecision {
+        if self.keys.push(key).is_err() {
+            return LeaderDecision::Cancelled
+        }
+        self.key_index = key_index;
+        self.deadline = now + Duration::from_millis(LEADER_TIMEOUT_MS);
+
+        let mut candidates = sequences.iter().filter(|seq| seq.keys.starts_with(&self.keys));
+        match candidates.next() {
+            None => LeaderDecision::Cancelled,
+            Some(seq) if seq.keys.len() == self.keys.len() && candidates.next().is_none() => {
+                LeaderDecision::Matched(seq.action)
+            },
+            Some(_) => LeaderDecision::Pending,
+        }
+    }
+
+    /// 捕获超时，已捕获的按键恰好匹配时触发
+    pub fn on_timeout(&self, sequences: &[LeaderSequence]) -> LeaderDecision {
+        sequences.iter()
+            .find(|seq| seq.keys == self.keys.as_slice())
+            .map_or(LeaderDecision::Cancelled, |seq| LeaderDecision::Matched(seq.action))
+    }
+}
@@ -0,0 +1,217 @@
+// 宏播放
+// 将宏步骤展开为逐个按下/松开的指令，由KbdCore在处理按键事件的间隙执行，
+// 不会阻塞按键事件的处理
+// 动态宏保存在VIA宏缓冲区中，格式与QMK一致，播放时逐个字节解析
+
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+
+use super::kbd::key::{KbdKey, ModifierKey, QwertyKey};
+use super::kbd::key_action::MacroStep;
+use super::scheduler::{Scheduler, Task};
+use super::unicode::UNICODE_COMMANDS_MAX;
+use crate::kbd_cfg::core::{DYNAMIC_MACRO_BUFFER_SIZE, DYNAMIC_MACRO_COUNT};
+
+/// 动态宏中的特殊指令前缀，之后一个字节为指令类型
+const SS_QMK_PREFIX: u8 = 0x01;
+const SS_TAP_CODE: u8 = 0x01;
+const SS_DOWN_CODE: u8 = 0x02;
+const SS_UP_CODE: u8 = 0x03;
+/// 延时，之后为十进制数字，以`|`结尾
+const SS_DELAY_CODE: u8 = 0x04;
+
+/// 宏播放指令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroCommand {
+    Press(KbdKey),
+    Release(KbdKey),
+}
+
+pub struct MacroPlayer {
+    steps: &'static [MacroStep],
+    /// 播放动态宏时为缓冲区中下一个待解析的位置
+    dynamic: Option<usize>,
+    /// 当前步骤
+    step: usize,
+    /// `MacroStep::Text`已输入的字符数
+    char_index: usize,
+    /// 当前步骤展开后待执行的指令
+    commands: Deque<MacroCommand, UNICODE_COMMANDS_MAX>,
+    /// 下一条指令的执行时间
+    next_at: Instant,
+}
+
+impl MacroPlayer {
+    /// 创建时登记第一条指令的执行任务
+    pub fn new(steps: &'static [MacroStep], now: Instant, scheduler: &mut Scheduler) -> Self {
+        scheduler.schedule(now, Task::MacroStep);
+        Self { steps, dynamic: None, step: 0, char_index: 0, commands: Deque::new(), next_at: now }
+    }
+
+    /// 播放动态宏，`offset`为宏在缓冲区中的起始位置
+    pub fn from_dynamic(offset: usize, now: Instant, scheduler: &mut Scheduler) -> Self {
+        Self { dynamic: Some(offset), ..Self::new(&[], now, scheduler) }
+    }
+
+    /// 直接播放指令序列，用于Unicode输入等动态生成的按键序列
+    pub fn from_commands(commands: impl IntoIterator<Item = MacroCommand>, now: Instant, scheduler: &mut Scheduler) -> Self {
+        let mut player = Self::new(&[], now, scheduler);
+        for command in commands {
+            let _ = player.commands.push_back(command);
+        }
+        player
+    }
+
+    /// 取出下一条指令，需要等待或已播放完毕时返回`None`
+    ///
+    /// 之后按下一条指令的执行时间重新登记任务，播放完毕时取消
+    pub fn next_command(&mut self, now: Instant, dynamic_macros: &DynamicMacros, scheduler: &mut Scheduler) -> Option<MacroCommand> {
+        let command = self.next(now, dynamic_macros);
+        scheduler.update((!self.is_finished()).then_some(self.next_at), Task::MacroStep);
+        command
+    }
+
+    fn next(&mut self, now: Instant, dynamic_macros: &DynamicMacros) -> Option<MacroCommand> {
+        while self.commands.is_empty() {
+            if now < self.next_at || self.is_finished() {
+                return None
+            }
+            match self.dynamic {
+                Some(offset) => self.expand_dynamic(offset, now, dynamic_macros),
+                None => self.expand_step(now),
+            }
+        }
+        self.commands.pop_front()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.commands.is_empty() && self.dynamic.is_none() && self.step >= self.steps.len()
+    }
+
+    /// 解析动态宏的一个步骤，遇到结尾或无法解析的内容时结束
+    fn expand_dynamic(&mut self, offset: usize, now: Instant, dynamic_macros: &DynamicMacros) {
+        self.dynamic = None;
+        let buffer = &dynamic_macros.buffer;
+        let byte = |index: usize| buffer.get(index).copied().unwrap_or(0);
+        let key = |index: usize| {
+            let key = KbdKey::from_code(byte(index));
+            if key.is_none() {
+                defmt::warn!("Unsupported dynamic macro keycode `{}`", byte(index));
+            }
+            key
+        };
+
+        let next = match (byte(offset), byte(offset + 1)) {
+            (0, _) => return,
+            (SS_QMK_PREFIX, SS_TAP_CODE) => key(offset + 2).map(|kbd_key| {
+                self.push(MacroCommand::Press(kbd_key));
+                self.push(MacroCommand::Release(kbd_key));
+                offset + 3
+            }),
+            (SS_QMK_PREFIX, SS_DOWN_CODE) => key(offset + 2).map(|kbd_key| {
+                self.push(MacroCommand::Press(kbd_key));
+                offset + 3
+            }),
+            (SS_QMK_PREFIX, SS_UP_CODE) => key(offset + 2).map(|kbd_key| {
+                self.push(MacroCommand::Release(kbd_key));
+                offset + 3
+            }),
+            (SS_QMK_PREFIX, SS_DELAY_CODE) => {
+                let mut index = offset + 2;
+                let mut ms: u64 = 0;
+                while byte(index).is_ascii_digit() {
+                    ms = ms.saturating_mul(10).saturating_add((byte(index) - b'0') as u64);
+                    index += 1;
+                }
+                self.next_at = now + Duration::from_millis(ms);
+                (byte(index) == b'|').then_some(index + 1)
+            },
+            (SS_QMK_PREFIX, code) => {
+                defmt::warn!("Unsupported dynamic macro code `{}`", code);
+                None
+            },
+            (c, _) => {
+                self.push_ascii(c);
+                Some(offset + 1)
+            },
+        };
+        self.dynamic = next;
+    }
+
+    fn push(&mut self, command: MacroCommand) {
+        let _ = self.commands.push_back(command);
+    }
+
+    /// 按下并松开ASCII字符对应的按键，需要时按住Shift
+    fn push_ascii(&mut self, c: u8) {
+        match QwertyKey::from_ascii(c) {
+            Some((key, shift)) => {
+                let shift_key: KbdKey = ModifierKey::LShift.into();
+                if shift { self.push(MacroCommand::Press(shift_key)); }
+                self.push(MacroCommand::Press(key.into()));
+                self.push(MacroCommand::Release(key.into()));
+                if shift { self.push(MacroCommand::Release(shift_key)); }
+            },
+            None => defmt::warn!("Unsupported macro character `{}`", c),
+        }
+    }
+
+    fn expand_step(&mut self, now: Instant) {
+        match self.steps[self.step] {
+            MacroStep::Tap(kbd_key) => {
+                self.push(MacroCommand::Press(kbd_key));
+                self.push(MacroCommand::Release(kbd_key));
+            },
+            MacroStep::Press(kbd_key) => self.push(MacroCommand::Press(kbd_key)),
+            MacroStep::Release(kbd_key) => self.push(MacroCommand::Release(kbd_key)),
+            MacroStep::Delay(ms) => self.next_at = now + Duration::from_millis(ms as u64),
+            MacroStep::Text(text) => {
+                // 每次只展开一个字符，字符之间可以穿插处理按键事件
+                if let Some(&c) = text.as_bytes().get(self.char_index) {
+                    self.char_index += 1;
+                    self.push_ascii(c);
+                    return
+                }
+                self.char_index = 0;
+            },
+        }
+        self.step += 1;
+    }
+}
+
+/// VIA宏缓冲区，多个宏依次存放，每个宏以0结尾
+pub struct DynamicMacros {
+    buffer: [u8; DYNAMIC_MACRO_BUFFER_SIZE],
+}
+
+impl Default for DynamicMacros {
+    fn default() -> Self {
+        Self { buffer: [0; DYNAMIC_MACRO_BUFFER_SIZE] }
+    }
+}
+
+impl DynamicMacros {
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.fill(0);
+    }
+
+    /// 第`index`个宏的起始位置，超出宏数量或缓冲区时返回`None`
+    pub fn find(&self, index: u8) -> Option<usize> {
+        if index >= DYNAMIC_MACRO_COUNT {
+            return None
+        }
+        let mut offset = 0;
+        for _ in 0..index {
+            offset += self.buffer.get(offset..)?.iter().position(|&b| b == 0)? + 1;
+        }
+        (offset < self.buffer.len()).then_some(offset)
+    }
+}
@@ -4,115 +4,793 @@
 pub(crate) mod channel;
 pub mod key_buffer;
 pub mod kbd;
+pub mod tap_dance;
+pub mod one_shot;
+pub mod layer_state;
+pub mod combo;
+pub mod macros;
+pub mod unicode;
+pub mod auto_shift;
+pub mod leader;
+pub mod caps_word;
+pub mod key_override;
+pub mod hold_tap;
+pub mod event_queue;
+pub mod scheduler;
+pub mod repeat;
+pub mod mouse_keys;
+pub mod extra_report;
+pub mod kbd_report;
+pub mod host_leds;
+pub mod status;
+pub mod via;
+pub mod settings;
+#[cfg(test)]
+mod tests;
 
-use crate::core::channel::{KEYBOARD_REPORT_CHANNEL, KEY_EVENT_CHANNEL};
+use crate::core::channel::{EXTRA_REPORT_CHANNEL, KEYBOARD_REPORT_CHANNEL, KEY_EVENT_CHANNEL, MOUSE_REPORT_CHANNEL};
+#[cfg(not(test))]
+use crate::core::channel::{HOST_LEDS_WATCH, VIA_REQUEST_CHANNEL, VIA_RESPONSE_CHANNEL};
 use crate::core::key_buffer::KeyBuffer;
+use crate::kbd_cfg::core::COMBO_MAX;
+use crate::kbd_cfg::storage::{SETTINGS_BUFFER_SIZE, SETTINGS_SAVE_DELAY_MS};
 
-use kbd::key::{KbdKey, LayerKey, QwertyKey, StateKey};
-use kbd::key_action::{KeyAction, UncertKey};
+use kbd::key::{KbdKey, LayerKey, ModifierKey, QwertyKey, StateKey};
+use kbd::key_action::{AltRepeat, Combo, HoldTap, KeyAction, KeyOverride, LeaderSequence, MacroStep, TapDance, UncertKey};
 use kbd::key_event::KeyEvent;
+use tap_dance::{TapDanceDecision, TapDanceState};
+use one_shot::{OneShotAction, OneShots};
+use layer_state::{LayerRule, LayerState};
+use combo::{ComboDecision, Combos};
+use event_queue::EventQueue;
+use scheduler::{Scheduler, Task};
+use mouse_keys::MouseKeys;
+use extra_report::ExtraReport;
+use macros::{DynamicMacros, MacroCommand, MacroPlayer};
+use unicode::UnicodeMode;
+use auto_shift::AutoShift;
+use leader::{Leader, LeaderDecision};
+use caps_word::CapsWord;
+use hold_tap::{HoldTapDecision, HoldTapState};
+use host_leds::HostLeds;
+use via::{ViaPacket, ViaTarget};
+use settings::{Settings, SettingsStore};
+use crate::kbp::indicator_led::{indicate_error, ERROR_STORAGE_WRITE};
+
+#[cfg(not(test))]
+use embassy_sync::watch::DynReceiver;
+use embassy_time::{Duration, Instant};
 
 pub type KeyMap<const KEY_NUM: usize, const LAYER_NUM: usize> = [[KeyAction; KEY_NUM]; LAYER_NUM];
 
+/// 布局附带的功能表，`KeyAction`中通过索引引用
+#[derive(Default)]
+pub struct KeyMapTables {
+    /// 待定键配置，对应`UncertKey::HT`
+    pub hold_taps: &'static [HoldTap],
+    /// 轻击舞配置，对应`KeyAction::TD`
+    pub tap_dances: &'static [TapDance],
+    /// 组合键配置，组合键触发时使用虚拟按键索引`KEY_NUM+组合键索引`
+    pub combos: &'static [Combo],
+    /// 宏配置，对应`KeyAction::Macro`
+    pub macros: &'static [&'static [MacroStep]],
+    /// Leader键序列
+    pub leader_sequences: &'static [LeaderSequence],
+    /// 按键覆盖规则，按顺序匹配
+    pub key_overrides: &'static [KeyOverride],
+    /// 条件层规则，层状态变化时重新计算
+    pub layer_rules: &'static [LayerRule],
+    /// 反向重复键对，对应`KeyAction::AltRepeat`
+    pub alt_repeats: &'static [AltRepeat],
+}
+
 pub struct KbdCore<const KEY_NUM: usize, const LAYER_NUM: usize> {
     /// 按键报文序列，用于维护按键顺序、构造按键报文
     key_buffer: KeyBuffer,
-    /// 待处理的未确定键
-    uncert_key: Option<(UncertKey, usize)>,
-    /// 键盘按键布局
+    /// 正在判定的待定键，判定期间的按键事件暂存在`event_queue`中
+    hold_tap: Option<HoldTapState>,
+    /// 最近一次判定为轻击的待定键及其松开时间，用于quick tap
+    last_tap: Option<(usize, Instant)>,
+    /// 最近一次按下按键的时间，用于prior idle
+    last_press_at: Instant,
+    /// 正在判定的轻击舞
+    tap_dance: Option<TapDanceState>,
+    /// One-shot状态键
+    one_shots: OneShots,
+    /// 组合键判定
+    combos: Combos<KEY_NUM>,
+    /// 正在播放的宏
+    macro_player: Option<MacroPlayer>,
+    /// VIA宏缓冲区
+    dynamic_macros: DynamicMacros,
+    /// Unicode输入方式
+    unicode_mode: UnicodeMode,
+    /// 自动Shift
+    auto_shift: AutoShift,
+    /// 正在捕获的Leader键序列
+    leader: Option<Leader>,
+    /// Caps Word
+    caps_word: CapsWord,
+    /// 触发按键覆盖的按键
+    override_key: Option<usize>,
+    /// 鼠标键
+    mouse_keys: MouseKeys,
+    /// 各类非键盘报文当前按下的用法，下标为报文ID-1
+    extra_usages: [u16; extra_report::EXTRA_REPORT_NUM],
+    /// 上一个发送的普通键及当时报文中的modifier，用于重复键
+    last_key: Option<(QwertyKey, u8)>,
+    /// 待处理的按键事件
+    event_queue: EventQueue,
+    /// 定时任务
+    scheduler: Scheduler,
+    /// 主机LED状态
+    host_leds: HostLeds,
+    /// 主机LED状态的接收端，状态变化时更新`host_leds`
+    #[cfg(not(test))]
+    host_leds_receiver: DynReceiver<'static, HostLeds>,
+    /// 键盘按键布局，可通过VIA修改
     key_map: KeyMap<KEY_NUM, LAYER_NUM>,
+    /// 初始布局，VIA重置布局时恢复
+    default_key_map: KeyMap<KEY_NUM, LAYER_NUM>,
+    /// 配置的持久化存储
+    settings_store: &'static mut dyn SettingsStore,
+    /// 序列化配置的缓冲区，与启动时加载配置共用
+    settings_buf: &'static mut [u8; SETTINGS_BUFFER_SIZE],
+    /// 布局附带的功能表
+    tables: KeyMapTables,
     /// 键盘Layer激活状态，高层优先级更高
-    layer_state: [bool; LAYER_NUM],
+    layer_state: LayerState,
     /// 按键动作缓存，用于在松开按键时撤销按键动作
     kbd_cache: [Option<KbdKey>; KEY_NUM],
+    /// 组合键的按键动作缓存
+    combo_cache: [Option<KbdKey>; COMBO_MAX],
 }
 
 impl<const KEY_NUM: usize, const LAYER_NUM: usize> KbdCore<KEY_NUM, LAYER_NUM> {
-    pub fn new(key_map: KeyMap<KEY_NUM, LAYER_NUM>) -> Self {
+    /// `settings`为启动时加载的配置，`default_key_map`为VIA重置布局时恢复的布局
+    pub fn new(
+        settings: Settings<KEY_NUM, LAYER_NUM>,
+        default_key_map: KeyMap<KEY_NUM, LAYER_NUM>,
+        tables: KeyMapTables,
+        settings_store: &'static mut dyn SettingsStore,
+        settings_buf: &'static mut [u8; SETTINGS_BUFFER_SIZE],
+    ) -> Self {
+        let mut auto_shift = AutoShift::default();
+        auto_shift.set_enabled(settings.auto_shift_enabled);
+        let mut layer_state = LayerState::default();
+        layer_state.set_default_layer(settings.default_layer);
         Self {
             key_buffer: KeyBuffer::default(),
-            uncert_key: None,
-            key_map,
-            layer_state: core::array::from_fn(|i| i==0),
+            hold_tap: None,
+            last_tap: None,
+            last_press_at: Instant::MIN,
+            tap_dance: None,
+            one_shots: OneShots::default(),
+            combos: Combos::default(),
+            macro_player: None,
+            dynamic_macros: settings.dynamic_macros,
+            unicode_mode: settings.unicode_mode,
+            auto_shift,
+            leader: None,
+            caps_word: CapsWord::default(),
+            override_key: None,
+            mouse_keys: MouseKeys::default(),
+            extra_usages: [0; _],
+            last_key: None,
+            event_queue: EventQueue::new(),
+            scheduler: Scheduler::default(),
+            host_leds: HostLeds::default(),
+            #[cfg(not(test))]
+            host_leds_receiver: HOST_LEDS_WATCH.dyn_receiver().unwrap(),
+            key_map: settings.key_map,
+            default_key_map,
+            settings_store,
+            settings_buf,
+            tables,
+            layer_state,
             kbd_cache: [None; KEY_NUM],
+            combo_cache: [None; COMBO_MAX],
         }
     }
 
     async fn send_kbd_report(&self) {
-        let report = self.key_buffer.get_cur_report();
+        let report = self.key_buffer.get_cur_report(kbd_report::is_boot_protocol());
         KEYBOARD_REPORT_CHANNEL.send(report).await
     }
 
+    async fn send_mouse_report(&self, report: usbd_hid::descriptor::MouseReport) {
+        MOUSE_REPORT_CHANNEL.send(report).await
+    }
+
+    async fn send_extra_report(&self, report: ExtraReport) {
+        EXTRA_REPORT_CHANNEL.send(report).await
+    }
+
+    #[cfg(not(test))]
     pub async fn run(mut self) {
         loop {
-            // 有待定键
-            if let Some((uncert_key, key_index)) = self.uncert_key.clone() {
-                self.process_with_uncert_key(uncert_key, key_index).await;
-            } else {
-                let event = KEY_EVENT_CHANNEL.receive().await;
-                self.process_event(event).await;
+            let event = self.next_event().await;
+            self.dispatch(event).await;
+        }
+    }
+
+    /// 处理一个按键事件，有待定判定时事件交给判定处理
+    async fn dispatch(&mut self, event: KeyEvent) {
+        if let Some(hold_tap) = self.hold_tap.take() {
+            self.process_with_hold_tap(hold_tap, event).await;
+        } else if let Some(tap_dance) = self.tap_dance.take() {
+            self.process_with_tap_dance(tap_dance, event).await;
+        } else if self.combos.is_pending() {
+            self.process_with_combo(event).await;
+        } else {
+            self.process_event(event).await;
+        }
+        status::publish_status(self.layer_state.bits(), self.one_shots.is_armed());
+    }
+
+    /// `now`为任务的处理时间，不晚于下一个待处理按键事件的时间
+    async fn process_task(&mut self, task: Task, now: Instant) {
+        match task {
+            Task::OneShotTimeout => self.process_one_shot_timeout(now).await,
+            Task::AutoShiftTimeout => self.process_auto_shift_timeout(now).await,
+            Task::LeaderTimeout => self.process_leader_timeout(now).await,
+            Task::CapsWordTimeout => self.caps_word.on_timeout(now),
+            // 宏和鼠标键按实际时间输出，不受积压事件影响
+            Task::MacroStep => self.process_macro().await,
+            Task::MouseKeysTick => {
+                if let Some(report) = self.mouse_keys.on_tick(Instant::now(), &mut self.scheduler) {
+                    self.send_mouse_report(report).await;
+                }
+            },
+            Task::HoldTapTimeout => {
+                if let Some(hold_tap) = self.hold_tap.take() {
+                    self.process_hold(hold_tap).await;
+                }
+            },
+            Task::TapDanceTimeout => {
+                if let Some(tap_dance) = self.tap_dance.take() {
+                    let decision = self.get_tap_dance(&tap_dance).map(|td| tap_dance.on_timeout(&td));
+                    self.process_tap_dance_decision(tap_dance, decision).await;
+                }
+            },
+            Task::ComboTimeout => {
+                let decision = self.combos.on_timeout(self.tables.combos, &self.layer_state);
+                self.process_combo_decision(decision);
+            },
+            Task::SaveSettings => self.save_settings(),
+        }
+        status::publish_status(self.layer_state.bits(), self.one_shots.is_armed());
+    }
+
+    /// 获取下一个按键事件，等待期间处理到时的定时任务、主机LED变化和VIA命令
+    #[cfg(not(test))]
+    async fn next_event(&mut self) -> KeyEvent {
+        loop {
+            if let Some(event) = self.poll_event(Instant::now()).await {
+                return event
+            }
+
+            let deadline = self.scheduler.next_deadline();
+            let receive = async {
+                match deadline {
+                    Some(at) => embassy_time::with_deadline(at, KEY_EVENT_CHANNEL.receive()).await.ok(),
+                    None => Some(KEY_EVENT_CHANNEL.receive().await),
+                }
+            };
+            // 等待期间主机LED变化或收到VIA命令时先处理，然后继续等待
+            use embassy_futures::select::{Either3, select3};
+            match select3(receive, self.host_leds_receiver.changed(), VIA_REQUEST_CHANNEL.receive()).await {
+                Either3::First(Some(event)) => self.event_queue.push_back(event),
+                Either3::First(None) => {},
+                Either3::Second(leds) => self.on_host_leds(leds),
+                Either3::Third(mut packet) => {
+                    self.process_via(&mut packet);
+                    VIA_RESPONSE_CHANNEL.send(packet).await;
+                },
             }
         }
     }
 
-    async fn process_with_uncert_key(&mut self, uncert_key: UncertKey, key_index: usize) {
-        self.uncert_key = None;
-        let mut process_inner = async |state_key: StateKey, qwerty_key: QwertyKey, event: KeyEvent| {
-            if event.key_index == (key_index as u8) {
-                let kbd_key: KbdKey = qwerty_key.into();
-                self.process_press_kbd_key(kbd_key, key_index).await;
-                self.process_release_kbd_key(kbd_key, key_index).await;
-            } else {
-                let kbd_key: KbdKey = state_key.into();
-                self.process_press_kbd_key(kbd_key, key_index).await;
-                self.process_event(event).await;
+    /// 不等待地取出下一个按键事件，没有可处理的事件时返回`None`
+    ///
+    /// 优先取出事件队列中待重放的事件，新收到的按键事件先放入队列再按顺序取出；
+    /// 早于下一个事件的定时任务(包括待定判定的超时)先于该事件处理，队列为空时处理`now`之前到时的任务
+    async fn poll_event(&mut self, now: Instant) -> Option<KeyEvent> {
+        loop {
+            while !self.event_queue.is_full() && let Ok(event) = KEY_EVENT_CHANNEL.try_receive() {
+                self.event_queue.push_back(event);
+            }
+
+            let now = self.event_queue.front().map_or(now, |event| event.time);
+            if let Some(task) = self.scheduler.pop_expired(now) {
+                self.process_task(task, now).await;
+                continue
+            }
+            if let Some(event) = self.event_queue.pop_front() {
+                return Some(event)
+            }
+            // 暂存的事件已占满队列，不再接收新事件，按超时结束待定判定，没有待定判定时直接重放
+            if self.event_queue.is_full() {
+                defmt::warn!("Event queue full, resolve pending key");
+                let task = if self.hold_tap.is_some() {
+                    Some(Task::HoldTapTimeout)
+                } else if self.tap_dance.is_some() {
+                    Some(Task::TapDanceTimeout)
+                } else if self.combos.is_pending() {
+                    Some(Task::ComboTimeout)
+                } else {
+                    None
+                };
+                match task {
+                    Some(task) => {
+                        self.scheduler.cancel(task);
+                        self.process_task(task, now).await;
+                    },
+                    None => self.event_queue.replay_held(),
+                }
+                continue
             }
+            return None
+        }
+    }
+
+    fn process_via(&mut self, packet: &mut ViaPacket) {
+        let mut target = ViaTarget {
+            key_map: &mut self.key_map,
+            default_key_map: &self.default_key_map,
+            dynamic_macros: &mut self.dynamic_macros,
+            auto_shift: &mut self.auto_shift,
+            unicode_mode: &mut self.unicode_mode,
+            layer_state: &mut self.layer_state,
         };
-        match uncert_key {
-            UncertKey::SK(state_key, qwerty_key) => {
-                let event = KEY_EVENT_CHANNEL.receive().await;
-                process_inner(state_key, qwerty_key, event).await;
+        if target.process(packet, Instant::now()) {
+            self.layer_state.apply_rules(self.tables.layer_rules, self.host_leds);
+            self.settings_changed();
+        }
+    }
+
+    /// 配置被修改，推迟到一段时间内没有新的修改后再保存
+    fn settings_changed(&mut self) {
+        self.scheduler.schedule(Instant::now() + Duration::from_millis(SETTINGS_SAVE_DELAY_MS), Task::SaveSettings);
+    }
+
+    /// 写入闪存期间会阻塞执行器，因此仅在修改配置后空闲时执行
+    fn save_settings(&mut self) {
+        let size = settings::serialize(
+            &self.key_map,
+            &self.dynamic_macros,
+            self.auto_shift.is_enabled(),
+            self.unicode_mode,
+            self.layer_state.default_layer(),
+            self.settings_buf,
+        );
+        if let Err(e) = self.settings_store.save(&self.settings_buf[..size]) {
+            defmt::error!("Failed to save settings: {}", defmt::Debug2Format(&e));
+            indicate_error(ERROR_STORAGE_WRITE);
+        }
+    }
+
+    /// 主机LED状态变化，重新计算依赖LED的条件层
+    #[cfg(not(test))]
+    fn on_host_leds(&mut self, leds: HostLeds) {
+        self.host_leds = leds;
+        self.layer_state.apply_rules(self.tables.layer_rules, leds);
+        status::publish_status(self.layer_state.bits(), self.one_shots.is_armed());
+    }
+
+    async fn process_with_hold_tap(&mut self, mut state: HoldTapState, event: KeyEvent) {
+        match state.on_event(&event, &mut self.scheduler) {
+            HoldTapDecision::Pending => {
+                self.event_queue.hold(event);
+                self.hold_tap = Some(state);
+            },
+            // 待定键松开判定为轻击，松开事件随轻击一起输出
+            HoldTapDecision::Tap => {
+                let kbd_key: KbdKey = state.hold_tap.qwerty_key.into();
+                self.process_press_kbd_key(kbd_key, state.key_index).await;
+                self.process_release_kbd_key(kbd_key, state.key_index).await;
+                self.last_tap = Some((state.key_index, event.time));
+                self.event_queue.replay_held();
+            },
+            HoldTapDecision::Hold => {
+                self.event_queue.hold(event);
+                self.process_hold(state).await;
+            },
+        }
+    }
+
+    /// 待定键判定为按住(包括超时)
+    async fn process_hold(&mut self, state: HoldTapState) {
+        self.process_press_kbd_key(state.hold_tap.state_key.into(), state.key_index).await;
+        // 暂存的事件按判定后的状态(如切换后的层)重新处理，其中的待定键、轻击舞会开始新的判定
+        self.event_queue.replay_held();
+    }
+
+    /// 待定键按下，轻击后快速再次按下或正在快速打字时直接按轻击键处理
+    async fn process_hold_tap_press(&mut self, uncert_key: &UncertKey, key_index: usize, now: Instant) {
+        let Some(hold_tap) = self.get_hold_tap(uncert_key) else {
+            defmt::error!("Undefined hold-tap `{}`", key_index);
+            return
+        };
+        if hold_tap::is_instant_tap(&hold_tap, key_index, self.last_tap, self.last_press_at, now) {
+            self.process_press_kbd_key(hold_tap.qwerty_key.into(), key_index).await;
+        } else {
+            self.hold_tap = Some(HoldTapState::new(key_index, hold_tap, now, &mut self.scheduler));
+        }
+    }
+
+    /// 待定键对应的配置，`sk`/`hk`视为hold-preferred
+    fn get_hold_tap(&self, uncert_key: &UncertKey) -> Option<HoldTap> {
+        match *uncert_key {
+            UncertKey::SK(state_key, qwerty_key) => Some(HoldTap {
+                tapping_term_ms: None,
+                ..HoldTap::new(state_key, qwerty_key, 0)
+            }),
+            UncertKey::HK(state_key, qwerty_key, time_ms) => Some(HoldTap::new(state_key, qwerty_key, time_ms)),
+            UncertKey::HT(index) => self.tables.hold_taps.get(index as usize).copied(),
+        }
+    }
+
+    fn get_tap_dance(&self, state: &TapDanceState) -> Option<TapDance> {
+        let tap_dance = self.tables.tap_dances.get(state.index as usize).copied();
+        if tap_dance.is_none() {
+            defmt::error!("Undefined tap dance `{}`", state.index);
+        }
+        tap_dance
+    }
+
+    async fn process_with_tap_dance(&mut self, mut state: TapDanceState, event: KeyEvent) {
+        let decision = self.get_tap_dance(&state).map(|td| state.on_event(&td, &event, &mut self.scheduler));
+        // 本键的事件计入连击，其他键的事件在判定后重放
+        if event.key_index as usize != state.key_index {
+            self.event_queue.hold(event);
+        }
+        self.process_tap_dance_decision(state, decision).await;
+    }
+
+    /// 轻击舞配置不存在(`None`)时直接结束判定
+    async fn process_tap_dance_decision(&mut self, state: TapDanceState, decision: Option<TapDanceDecision>) {
+        match decision {
+            Some(TapDanceDecision::Pending) => {
+                self.tap_dance = Some(state);
+                return
             },
-            UncertKey::HK(state_key, qwerty_key, time_ms) => {
-                let time_ms = embassy_time::Duration::from_millis(time_ms as u64);
-                let timeout_fut = embassy_time::with_timeout(time_ms,
-                    KEY_EVENT_CHANNEL.receive()
-                );
-                match timeout_fut.await {
-                    Ok(event) => process_inner(state_key, qwerty_key, event).await,
-                    Err(_) => self.process_press_kbd_key(state_key.into(), key_index).await,
+            Some(TapDanceDecision::Resolved(Some(kbd_key))) => {
+                self.process_press_kbd_key(kbd_key, state.key_index).await;
+                if !state.is_pressed {
+                    self.process_release_kbd_key(kbd_key, state.key_index).await;
                 }
             },
+            Some(TapDanceDecision::Resolved(None)) => {},
+            None => self.scheduler.cancel(Task::TapDanceTimeout),
+        }
+        self.event_queue.replay_held();
+    }
+
+    async fn process_with_combo(&mut self, event: KeyEvent) {
+        self.event_queue.hold(event);
+        let decision = self.combos.on_event(self.tables.combos, &self.layer_state, &event, &mut self.scheduler);
+        self.process_combo_decision(decision);
+    }
+
+    fn process_combo_decision(&mut self, decision: ComboDecision) {
+        match decision {
+            ComboDecision::Pending => {},
+            // 虚拟按键事件代替成员键按下事件，排在其余暂存事件之前
+            ComboDecision::Fired { event, key_num } => {
+                self.event_queue.discard_held(key_num);
+                self.event_queue.replay_held();
+                self.event_queue.replay(&[event]);
+            },
+            ComboDecision::Failed => self.event_queue.replay_held(),
+        }
+    }
+
+    async fn process_one_shot_timeout(&mut self, now: Instant) {
+        while let Some(state_key) = self.one_shots.pop_expired(now, &mut self.scheduler) {
+            self.release_kbd_key(state_key.into()).await;
+        }
+    }
+
+    /// 执行一条宏指令，每次只执行一条，避免长时间不处理按键事件
+    async fn process_macro(&mut self) {
+        let Some(player) = self.macro_player.as_mut() else { return };
+        let command = player.next_command(Instant::now(), &self.dynamic_macros, &mut self.scheduler);
+        let is_finished = player.is_finished();
+        if is_finished {
+            self.macro_player = None;
+        }
+
+        match command {
+            // Unicode输入序列的修饰键与用户按住的修饰键分开记录
+            Some(MacroCommand::Press(KbdKey::State(StateKey::Modifier(modifier_key))))
+                if let Some(modifier) = self.key_buffer.macro_modifier() => {
+                self.key_buffer.set_macro_modifier(Some(modifier | modifier_key.bit()));
+                self.send_kbd_report().await;
+            },
+            Some(MacroCommand::Release(KbdKey::State(StateKey::Modifier(modifier_key))))
+                if let Some(modifier) = self.key_buffer.macro_modifier() => {
+                self.key_buffer.set_macro_modifier(Some(modifier & !modifier_key.bit()));
+                self.send_kbd_report().await;
+            },
+            Some(MacroCommand::Press(kbd_key)) => self.press_kbd_key(kbd_key).await,
+            Some(MacroCommand::Release(kbd_key)) => self.release_kbd_key(kbd_key).await,
+            None => {},
+        }
+        // 序列播放完毕，恢复用户按住的修饰键
+        if is_finished && self.key_buffer.macro_modifier().is_some() {
+            self.key_buffer.set_macro_modifier(None);
+            self.send_kbd_report().await;
+        }
+    }
+
+    async fn process_one_shot_action(&mut self, action: OneShotAction) {
+        match action {
+            OneShotAction::Activate(state_key) => self.press_kbd_key(state_key.into()).await,
+            OneShotAction::Deactivate(state_key) => self.release_kbd_key(state_key.into()).await,
+            OneShotAction::None => {},
         }
     }
 
     async fn process_event(&mut self, event: KeyEvent) {
+        // 组合键成员键的按下先暂存等待判定，已触发组合键的成员键松开时转换为虚拟按键松开
+        let event = if event.is_pressed {
+            if self.combos.on_press(self.tables.combos, &self.layer_state, &event, &mut self.scheduler) {
+                self.event_queue.hold(event);
+                return
+            }
+            event
+        } else {
+            let Some(event) = self.combos.on_release(self.tables.combos, event) else { return };
+            event
+        };
+
         let key_index = event.key_index as usize;
+        let now = event.time;
         if !event.is_pressed {
-            if let Some(kbd_key) = self.kbd_cache[key_index] {
+            if self.auto_shift.is_pending(key_index) {
+                self.process_auto_shift_release(key_index).await;
+            } else if let Some(action) = self.one_shots.on_release(key_index, now, &mut self.scheduler) {
+                self.process_one_shot_action(action).await;
+            } else if let Some(kbd_key) = *self.cache_mut(key_index) {
                 self.process_release_kbd_key(kbd_key, key_index).await;
             }
         } else {
-            let action = self.get_press_action(key_index).await;
-            self.process_press_action(&action, key_index).await;
+            self.process_press_event(key_index, now).await;
+            self.last_press_at = now;
+        }
+    }
+
+    async fn process_press_event(&mut self, key_index: usize, now: Instant) {
+        self.one_shots.on_other_press(key_index);
+        let action = self.get_press_action(key_index).await;
+        if let Some(leader) = self.leader.as_mut() {
+            // 捕获普通键，状态键照常处理(可以通过切层输入序列)
+            match action {
+                KeyAction::CK(KbdKey::Normal(qwerty_key)) => {
+                    let decision = leader.on_key(self.tables.leader_sequences, key_index, qwerty_key, now, &mut self.scheduler);
+                    self.process_leader_decision(decision, now).await;
+                },
+                KeyAction::CK(KbdKey::State(_)) => self.process_press_action(&action, key_index, now).await,
+                _ => {},
+            }
+            return
+        }
+        if let KeyAction::CK(KbdKey::Normal(qwerty_key)) = action
+            && self.auto_shift.is_eligible(qwerty_key, self.key_buffer.modifier())
+            && self.auto_shift.on_press(key_index, qwerty_key, now, &mut self.scheduler) {
+            return
+        }
+        // 按下其他键时，待定的自动Shift键按普通键按下
+        while let Some(pending) = self.auto_shift.pop_front(&mut self.scheduler) {
+            self.process_press_kbd_key(pending.qwerty_key.into(), pending.key_index).await;
+        }
+        self.process_press_action(&action, key_index, now).await;
+    }
+
+    async fn process_leader_timeout(&mut self, now: Instant) {
+        if let Some(leader) = self.leader.as_ref() && leader.deadline() <= now {
+            let decision = leader.on_timeout(self.tables.leader_sequences);
+            self.process_leader_decision(decision, now).await;
+        }
+    }
+
+    /// 匹配成功时触发动作，普通按键动作视为轻击
+    async fn process_leader_decision(&mut self, decision: LeaderDecision, now: Instant) {
+        match decision {
+            LeaderDecision::Pending => {},
+            LeaderDecision::Cancelled => self.leader = None,
+            LeaderDecision::Matched(action) => {
+                let key_index = self.leader.take().map_or(0, |leader| leader.key_index);
+                match action {
+                    KeyAction::CK(kbd_key) => {
+                        self.press_kbd_key(kbd_key).await;
+                        self.release_kbd_key(kbd_key).await;
+                    },
+                    _ => self.process_press_action(&action, key_index, now).await,
+                }
+            },
         }
     }
 
-    async fn process_press_action(&mut self, action: &KeyAction, key_index: usize) {
+    /// 在阈值内松开，输出原字符。之前按下的待定键按普通键按下，保证输出顺序
+    async fn process_auto_shift_release(&mut self, key_index: usize) {
+        while let Some(pending) = self.auto_shift.pop_front(&mut self.scheduler) {
+            let kbd_key: KbdKey = pending.qwerty_key.into();
+            self.process_press_kbd_key(kbd_key, pending.key_index).await;
+            if pending.key_index == key_index {
+                self.process_release_kbd_key(kbd_key, key_index).await;
+                break;
+            }
+        }
+    }
+
+    /// 按住超过阈值，输出Shift后的字符，松开按键时无动作
+    async fn process_auto_shift_timeout(&mut self, now: Instant) {
+        let shift: KbdKey = ModifierKey::LShift.into();
+        while let Some(pending) = self.auto_shift.pop_expired(now, &mut self.scheduler) {
+            let kbd_key: KbdKey = pending.qwerty_key.into();
+            self.press_kbd_key(shift).await;
+            self.process_press_kbd_key(kbd_key, pending.key_index).await;
+            self.process_release_kbd_key(kbd_key, pending.key_index).await;
+            self.release_kbd_key(shift).await;
+        }
+    }
+
+    async fn process_press_action(&mut self, action: &KeyAction, key_index: usize, now: Instant) {
         match action {
             KeyAction::CK(kbd_key) => {
                 self.process_press_kbd_key(*kbd_key, key_index).await;
             },
             KeyAction::UK(uncert_key) => {
-                self.uncert_key = Some((uncert_key.clone(), key_index));
+                self.process_hold_tap_press(uncert_key, key_index, now).await;
+            }
+            KeyAction::TD(index) => {
+                if let Some(tap_dance) = self.tables.tap_dances.get(*index as usize) {
+                    self.tap_dance = Some(TapDanceState::new(*index, key_index, tap_dance, now, &mut self.scheduler));
+                } else {
+                    defmt::error!("Undefined tap dance `{}`", index);
+                }
+            }
+            KeyAction::OS(state_key) => {
+                let action = self.one_shots.on_press(*state_key, key_index, &mut self.scheduler);
+                self.process_one_shot_action(action).await;
+            }
+            KeyAction::Macro(index) => {
+                if self.macro_player.is_some() {
+                    defmt::warn!("Macro is playing, ignore macro `{}`", index);
+                } else if let Some(steps) = self.tables.macros.get(*index as usize) {
+                    self.macro_player = Some(MacroPlayer::new(steps, Instant::now(), &mut self.scheduler));
+                } else {
+                    defmt::error!("Undefined macro `{}`", index);
+                }
+            }
+            KeyAction::DynMacro(index) => {
+                if self.macro_player.is_some() {
+                    defmt::warn!("Macro is playing, ignore dynamic macro `{}`", index);
+                } else if let Some(offset) = self.dynamic_macros.find(*index) {
+                    self.macro_player = Some(MacroPlayer::from_dynamic(offset, Instant::now(), &mut self.scheduler));
+                } else {
+                    defmt::error!("Undefined dynamic macro `{}`", index);
+                }
+            }
+            KeyAction::Unicode(c) => {
+                if self.macro_player.is_some() {
+                    defmt::warn!("Macro is playing, ignore unicode `{}`", *c as u32);
+                } else {
+                    let commands = unicode::input_commands(self.unicode_mode, *c);
+                    self.key_buffer.set_macro_modifier(Some(0));
+                    self.macro_player = Some(MacroPlayer::from_commands(commands, Instant::now(), &mut self.scheduler));
+                }
+            }
+            KeyAction::UnicodeMode(mode) => {
+                self.unicode_mode = *mode;
+                self.settings_changed();
+            }
+            KeyAction::AutoShiftToggle => {
+                self.auto_shift.toggle();
+                self.settings_changed();
+            }
+            KeyAction::Leader => {
+                self.leader = Some(Leader::new(key_index, now, &mut self.scheduler));
             }
-            KeyAction::NA => {},
-            KeyAction::TS => unreachable!(),
+            KeyAction::CapsWord => {
+                self.caps_word.toggle(now, &mut self.scheduler);
+            }
+            KeyAction::Repeat | KeyAction::AltRepeat => {
+                self.process_repeat(*action == KeyAction::AltRepeat, key_index).await;
+            }
+            // 透明键只在查找布局时跳过，其他来源(如Leader序列)的透明键视为无动作
+            KeyAction::NA | KeyAction::TS => {},
         };
     }
 
-    async fn process_press_kbd_key(&mut self, kbd_key: KbdKey, key_index: usize) {
+    async fn process_press_kbd_key(&mut self, mut kbd_key: KbdKey, key_index: usize) {
+        self.end_override().await;
+        if let KbdKey::Normal(qwerty_key) = kbd_key
+            && let Some((replacement, modifier)) = key_override::find_override(self.tables.key_overrides, qwerty_key, self.key_buffer.modifier()) {
+            self.key_buffer.set_modifier_override(Some(modifier));
+            self.override_key = Some(key_index);
+            kbd_key = replacement.into();
+        }
+
+        // Caps Word只对本次按下加Shift，不影响之后的报文
+        let modifier = self.key_buffer.modifier();
+        let caps_shift = self.caps_word.on_key(kbd_key, modifier, self.host_leds.caps_lock(), Instant::now(), &mut self.scheduler)
+            && modifier & ModifierKey::LShift.bit() == 0;
+
+        if caps_shift {
+            self.key_buffer.set_modifier(ModifierKey::LShift as u8);
+        }
+        self.press_kbd_key(kbd_key).await;
+        if let KbdKey::Normal(qwerty_key) = kbd_key {
+            self.last_key = Some((qwerty_key, self.key_buffer.report_modifier()));
+        }
+        if caps_shift {
+            self.key_buffer.unset_modifier(ModifierKey::LShift as u8);
+        }
+        *self.cache_mut(key_index) = Some(kbd_key);
+
+        // 普通键按下后撤销已轻击的one-shot键
+        if let KbdKey::Normal(_) = kbd_key {
+            while let Some(state_key) = self.one_shots.pop_armed(&mut self.scheduler) {
+                self.release_kbd_key(state_key.into()).await;
+            }
+        }
+    }
+
+    /// 以记录的modifier发送上一个普通键(或其反向键)，松开重复键或按下其他键时恢复原修饰键
+    async fn process_repeat(&mut self, is_alt: bool, key_index: usize) {
+        self.end_override().await;
+        let Some((mut qwerty_key, modifier)) = self.last_key else { return };
+        if is_alt {
+            match repeat::find_alt(self.tables.alt_repeats, qwerty_key) {
+                Some(alt) => qwerty_key = alt,
+                None => return,
+            }
+        }
+
+        self.key_buffer.set_modifier_override(Some(modifier));
+        self.override_key = Some(key_index);
+        self.press_kbd_key(qwerty_key.into()).await;
+        *self.cache_mut(key_index) = Some(qwerty_key.into());
+    }
+
+    /// 按下其他键时结束按键覆盖：先松开替换键，再恢复原修饰键，避免原修饰键与替换键同时发送
+    async fn end_override(&mut self) {
+        let Some(key_index) = self.override_key.take() else { return };
+        if let Some(kbd_key) = self.cache_mut(key_index).take() {
+            self.release_kbd_key(kbd_key).await;
+        }
+        self.key_buffer.set_modifier_override(None);
+    }
+
+    async fn process_release_kbd_key(&mut self, kbd_key: KbdKey, key_index: usize) {
+        if self.override_key == Some(key_index) {
+            self.override_key = None;
+            self.key_buffer.set_modifier_override(None);
+        }
+        self.release_kbd_key(kbd_key).await;
+        *self.cache_mut(key_index) = None;
+    }
+
+    /// 按键动作缓存，组合键的虚拟按键使用单独的缓存
+    fn cache_mut(&mut self, key_index: usize) -> &mut Option<KbdKey> {
+        match Combos::<KEY_NUM>::combo_index(key_index) {
+            Some(combo_index) => &mut self.combo_cache[combo_index],
+            None => &mut self.kbd_cache[key_index],
+        }
+    }
+
+    async fn press_kbd_key(&mut self, kbd_key: KbdKey) {
         match kbd_key {
+            KbdKey::Normal(qwerty_key) if MouseKeys::is_mouse_key(qwerty_key) => {
+                if self.mouse_keys.on_press(qwerty_key, Instant::now(), &mut self.scheduler) {
+                    self.send_mouse_report(self.mouse_keys.report()).await;
+                }
+            },
+            KbdKey::Normal(_) if let Some(report) = ExtraReport::from_kbd_key(kbd_key) => self.press_extra(report).await,
+            KbdKey::Consumer(usage) => self.press_extra(ExtraReport::Consumer(usage)).await,
             KbdKey::Normal(qwerty_key) => {
                 self.key_buffer.presse_key(qwerty_key as u8);
                 self.send_kbd_report().await;
@@ -121,22 +799,36 @@ impl<const KEY_NUM: usize, const LAYER_NUM: usize> KbdCore<KEY_NUM, LAYER_NUM> {
                 self.key_buffer.set_modifier(modifier_key as u8);
                 self.send_kbd_report().await;
             },
+            // 层数以外的层(如VIA键码中的层)不激活，否则默认层可能被切到不存在的层
+            KbdKey::State(StateKey::Layer(layer_key)) if layer_key.layer() as usize >= LAYER_NUM => {
+                defmt::warn!("Layer `{}` out of range, ignore layer key", layer_key.layer());
+            },
             KbdKey::State(StateKey::Layer(layer_key)) => {
                 match layer_key {
-                    LayerKey::LayerOn(layer) => {
-                        self.layer_state[layer as usize] = true;
-                    },
-                    LayerKey::LayerSwitch(layer) => {
-                        self.layer_state[layer as usize] = true;
+                    LayerKey::LayerOn(layer) => self.layer_state.layer_on(layer),
+                    LayerKey::LayerSwitch(layer) => self.layer_state.layer_toggle(layer),
+                    LayerKey::LayerTo(layer) => self.layer_state.layer_move(layer),
+                    // 默认层与布局一起保存
+                    LayerKey::DefaultLayer(layer) if layer != self.layer_state.default_layer() => {
+                        self.layer_state.set_default_layer(layer);
+                        self.settings_changed();
                     },
+                    LayerKey::DefaultLayer(_) => {},
                 }
+                self.layer_state.apply_rules(self.tables.layer_rules, self.host_leds);
             },
         }
-        self.kbd_cache[key_index] = Some(kbd_key);
     }
 
-    async fn process_release_kbd_key(&mut self, kbd_key: KbdKey, key_index: usize) {
+    async fn release_kbd_key(&mut self, kbd_key: KbdKey) {
         match kbd_key {
+            KbdKey::Normal(qwerty_key) if MouseKeys::is_mouse_key(qwerty_key) => {
+                if self.mouse_keys.on_release(qwerty_key, &mut self.scheduler) {
+                    self.send_mouse_report(self.mouse_keys.report()).await;
+                }
+            },
+            KbdKey::Normal(_) if let Some(report) = ExtraReport::from_kbd_key(kbd_key) => self.release_extra(report).await,
+            KbdKey::Consumer(usage) => self.release_extra(ExtraReport::Consumer(usage)).await,
             KbdKey::Normal(qwerty_key) => {
                 self.key_buffer.release_key(qwerty_key as u8);
                 self.send_kbd_report().await;
@@ -145,22 +837,39 @@ impl<const KEY_NUM: usize, const LAYER_NUM: usize> KbdCore<KEY_NUM, LAYER_NUM> {
                 self.key_buffer.unset_modifier(modifier_key as u8);
                 self.send_kbd_report().await;
             },
+            KbdKey::State(StateKey::Layer(layer_key)) if layer_key.layer() as usize >= LAYER_NUM => {},
             KbdKey::State(StateKey::Layer(layer_key)) => {
                 match layer_key {
-                    LayerKey::LayerOn(layer) => {
-                        self.layer_state[layer as usize] = false;
-                    },
-                    LayerKey::LayerSwitch(_layer) => {
-                    },
+                    LayerKey::LayerOn(layer) => self.layer_state.layer_off(layer),
+                    // 仅在按下时生效
+                    LayerKey::LayerSwitch(_) | LayerKey::LayerTo(_) | LayerKey::DefaultLayer(_) => {},
                 }
+                self.layer_state.apply_rules(self.tables.layer_rules, self.host_leds);
             },
         }
-        self.kbd_cache[key_index] = None;
+    }
+
+    /// 每类报文只能包含一个用法，后按下的覆盖先按下的
+    async fn press_extra(&mut self, report: ExtraReport) {
+        self.extra_usages[report.report_id() as usize - 1] = report.usage();
+        self.send_extra_report(report).await;
+    }
+
+    async fn release_extra(&mut self, report: ExtraReport) {
+        let usage = &mut self.extra_usages[report.report_id() as usize - 1];
+        if *usage == report.usage() {
+            *usage = 0;
+            self.send_extra_report(report.released()).await;
+        }
     }
 
     async fn get_press_action(&self, key_index: usize) -> KeyAction {
+        if let Some(combo_index) = Combos::<KEY_NUM>::combo_index(key_index) {
+            return self.tables.combos[combo_index].action
+        }
+
         for (layer_idx, key_map) in self.key_map.iter().enumerate().rev() {
-            if self.layer_state[layer_idx] {
+            if self.layer_state.is_active(layer_idx as u8) {
                 let action = &key_map[key_index];
                 if *action == KeyAction::TS {
                     continue;
@@ -0,0 +1,193 @@
+// 鼠标键
+// 用`QwertyKey::Mouse*`模拟鼠标：按键直接改变按钮状态，方向键和滚轮按固定间隔输出移动量
+// 移动速度随按住时间按加速曲线增加，按住`MouseAccel0~2`时使用对应的恒定速度
+
+use embassy_time::{Duration, Instant};
+use usbd_hid::descriptor::MouseReport;
+
+use super::kbd::key::QwertyKey;
+use super::scheduler::{Scheduler, Task};
+use crate::kbd_cfg::core::{
+    MOUSE_KEYS_CONSTANT_SPEEDS, MOUSE_KEYS_CURVE, MOUSE_KEYS_INTERVAL_MS, MOUSE_KEYS_MAX_SPEED,
+    MOUSE_KEYS_MIN_SPEED, MOUSE_KEYS_TIME_TO_MAX_MS, MOUSE_WHEEL_INTERVAL_MS, MOUSE_WHEEL_SPEED,
+};
+
+/// 移动速度随按住时间变化的曲线
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseCurve {
+    /// 匀速，始终为最低速度
+    Constant,
+    /// 线性加速到最高速度
+    Linear,
+    /// 二次曲线加速，起步更慢，便于精确定位
+    Quadratic,
+}
+
+/// 方向位，依次为上、下、左、右
+const UP: u8 = 1 << 0;
+const DOWN: u8 = 1 << 1;
+const LEFT: u8 = 1 << 2;
+const RIGHT: u8 = 1 << 3;
+
+#[derive(Default)]
+pub struct MouseKeys {
+    /// 按住的鼠标按钮
+    buttons: u8,
+    /// 按住的移动方向
+    moves: u8,
+    /// 按住的滚轮方向
+    wheels: u8,
+    /// 按住的恒定速度键，第n位对应`MouseAccel{n}`
+    accels: u8,
+    /// 开始移动的时间，用于计算加速
+    move_start: Option<Instant>,
+    /// 下一次输出移动量的时间
+    next_tick: Option<Instant>,
+    /// 下一次输出滚轮的时间
+    next_wheel: Option<Instant>,
+}
+
+impl MouseKeys {
+    pub fn is_mouse_key(qwerty_key: QwertyKey) -> bool {
+        (QwertyKey::MouseUp as u8..=QwertyKey::MouseAccel2 as u8).contains(&(qwerty_key as u8))
+    }
+
+    /// 按下鼠标键，按钮状态变化时返回`true`，调用方需发送报文
+    pub fn on_press(&mut self, qwerty_key: QwertyKey, now: Instant, scheduler: &mut Scheduler) -> bool {
+        let changed = self.press(qwerty_key, now);
+        scheduler.update(self.next_tick, Task::MouseKeysTick);
+        changed
+    }
+
+    fn press(&mut self, qwerty_key: QwertyKey, now: Instant) -> bool {
+        use QwertyKey::*;
+        match qwerty_key {
+            MouseUp | MouseDown | MouseLeft | MouseRight => {
+                if self.moves == 0 {
+                    self.move_start = Some(now);
+                }
+                self.moves |= Self::direction_bit(qwerty_key);
+                self.next_tick.get_or_insert(now);
+            },
+            MouseWheelUp | MouseWheelDown | MouseWheelLeft | MouseWheelRight => {
+                self.wheels |= Self::direction_bit(qwerty_key);
+                self.next_wheel.get_or_insert(now);
+                self.next_tick.get_or_insert(now);
+            },
+            MouseAccel0 | MouseAccel1 | MouseAccel2 => {
+                self.accels |= 1 << (qwerty_key as u8 - MouseAccel0 as u8);
+            },
+            _ => {
+                self.buttons |= 1 << (qwerty_key as u8 - MouseBtn1 as u8);
+                return true
+            },
+        }
+        false
+    }
+
+    /// 松开鼠标键，按钮状态变化时返回`true`，调用方需发送报文
+    pub fn on_release(&mut self, qwerty_key: QwertyKey, scheduler: &mut Scheduler) -> bool {
+        let changed = self.release(qwerty_key);
+        scheduler.update(self.next_tick, Task::MouseKeysTick);
+        changed
+    }
+
+    fn release(&mut self, qwerty_key: QwertyKey) -> bool {
+        use QwertyKey::*;
+        match qwerty_key {
+            MouseUp | MouseDown | MouseLeft | MouseRight => {
+                self.moves &= !Self::direction_bit(qwerty_key);
+                if self.moves == 0 {
+                    self.move_start = None;
+                }
+            },
+            MouseWheelUp | MouseWheelDown | MouseWheelLeft | MouseWheelRight => {
+                self.wheels &= !Self::direction_bit(qwerty_key);
+                if self.wheels == 0 {
+                    self.next_wheel = None;
+                }
+            },
+            MouseAccel0 | MouseAccel1 | MouseAccel2 => {
+                self.accels &= !(1 << (qwerty_key as u8 - MouseAccel0 as u8));
+            },
+            _ => {
+                self.buttons &= !(1 << (qwerty_key as u8 - MouseBtn1 as u8));
+                return true
+            },
+        }
+        if self.moves == 0 && self.wheels == 0 {
+            self.next_tick = None;
+        }
+        false
+    }
+
+    /// 只包含按钮状态的报文
+    pub fn report(&self) -> MouseReport {
+        MouseReport { buttons: self.buttons, x: 0, y: 0, wheel: 0, pan: 0 }
+    }
+
+    /// 到达输出间隔，生成包含移动量的报文，没有移动量时返回`None`
+    pub fn on_tick(&mut self, now: Instant, scheduler: &mut Scheduler) -> Option<MouseReport> {
+        self.next_tick.filter(|&at| at <= now)?;
+        self.next_tick = Some(now + Duration::from_millis(MOUSE_KEYS_INTERVAL_MS));
+        scheduler.update(self.next_tick, Task::MouseKeysTick);
+
+        let mut report = self.report();
+        if let Some(move_start) = self.move_start {
+            let (x, y) = Self::axes(self.moves);
+            let mut speed = self.speed(now - move_start) as i16;
+            // 斜向移动时每个轴乘以约1/√2，保持速度一致
+            if x != 0 && y != 0 {
+                speed = (speed * 181 / 256).max(1);
+            }
+            report.x = (x * speed).clamp(-127, 127) as i8;
+            report.y = (y * speed).clamp(-127, 127) as i8;
+        }
+        if let Some(next_wheel) = self.next_wheel && next_wheel <= now {
+            self.next_wheel = Some(now + Duration::from_millis(MOUSE_WHEEL_INTERVAL_MS));
+            // 滚轮向上为正，与移动方向的y轴相反
+            let (pan, wheel) = Self::axes(self.wheels);
+            report.wheel = -wheel as i8 * MOUSE_WHEEL_SPEED;
+            report.pan = pan as i8 * MOUSE_WHEEL_SPEED;
+        }
+
+        let is_moved = report.x != 0 || report.y != 0 || report.wheel != 0 || report.pan != 0;
+        is_moved.then_some(report)
+    }
+
+    /// 每次输出的移动量
+    fn speed(&self, held: Duration) -> u32 {
+        if self.accels != 0 {
+            // 同时按住多个时取最快的
+            let index = 7 - self.accels.leading_zeros() as usize;
+            return MOUSE_KEYS_CONSTANT_SPEEDS[index] as u32
+        }
+
+        let (min, max) = (MOUSE_KEYS_MIN_SPEED as u64, MOUSE_KEYS_MAX_SPEED as u64);
+        let t = held.as_millis().min(MOUSE_KEYS_TIME_TO_MAX_MS);
+        let total = MOUSE_KEYS_TIME_TO_MAX_MS.max(1);
+        let speed = match MOUSE_KEYS_CURVE {
+            MouseCurve::Constant => min,
+            MouseCurve::Linear => min + (max - min) * t / total,
+            MouseCurve::Quadratic => min + (max - min) * t * t / (total * total),
+        };
+        speed as u32
+    }
+
+    /// 方向位对应的(x, y)方向，向右、向下为正
+    fn axes(directions: u8) -> (i16, i16) {
+        let axis = |neg: u8, pos: u8| (directions & pos != 0) as i16 - (directions & neg != 0) as i16;
+        (axis(LEFT, RIGHT), axis(UP, DOWN))
+    }
+
+    fn direction_bit(qwerty_key: QwertyKey) -> u8 {
+        use QwertyKey::*;
+        match qwerty_key {
+            MouseUp | MouseWheelUp => UP,
+            MouseDown | MouseWheelDown => DOWN,
+            MouseLeft | MouseWheelLeft => LEFT,
+            _ => RIGHT,
+        }
+    }
+}
@@ -0,0 +1,259 @@
+// One-shot状态键逻辑
+// 轻击one-shot键后，对应的状态键仅作用于下一个普通键，之后自动撤销
+// 与IO无关，调用方根据返回值激活/撤销状态键
+
+use embassy_time::{Duration, Instant};
+
+use super::kbd::key::StateKey;
+use super::scheduler::{Scheduler, Task};
+use crate::kbd_cfg::core::{ONE_SHOT_MAX, ONE_SHOT_TAP_TO_LOCK, ONE_SHOT_TIMEOUT_MS};
+
+/// 调用方需要执行的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OneShotAction {
+    /// 激活状态键
+    Activate(StateKey),
+    /// 撤销状态键
+    Deactivate(StateKey),
+    /// 无动作
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OneShotStatus {
+    /// 按住中，`interrupted`表示按住期间按下了其他键，此时退化为普通的按住激活
+    Held { interrupted: bool },
+    /// 已轻击，等待作用于下一个普通键
+    Armed { deadline: Instant },
+    /// 双击锁定，再次按下时解除
+    Locked,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OneShot {
+    key: StateKey,
+    key_index: usize,
+    status: OneShotStatus,
+}
+
+#[derive(Default)]
+pub struct OneShots {
+    slots: [Option<OneShot>; ONE_SHOT_MAX],
+}
+
+impl OneShots {
+    /// 按下one-shot键
+    pub fn on_press(&mut self, key: StateKey, key_index: usize, scheduler: &mut Scheduler) -> OneShotAction {
+        let action = self.press(key, key_index);
+        self.reschedule(scheduler);
+        action
+    }
+
+    fn press(&mut self, key: StateKey, key_index: usize) -> OneShotAction {
+        if let Some(slot) = self.slots.iter_mut().find(|slot| matches!(slot, Some(one_shot) if one_shot.key == key)) {
+            let one_shot = slot.as_mut().unwrap();
+            return match one_shot.status {
+                OneShotStatus::Armed { .. } if ONE_SHOT_TAP_TO_LOCK => {
+                    one_shot.status = OneShotStatus::Locked;
+                    one_shot.key_index = key_index;
+                    OneShotAction::None
+                },
+                // 再次按下，解除锁定/撤销待作用的键
+                _ => {
+                    *slot = None;
+                    OneShotAction::Deactivate(key)
+                },
+            };
+        }
+
+        let Some(slot) = self.slots.iter_mut().find(|slot| slot.is_none()) else {
+            defmt::warn!("one-shot slots full, ignore one-shot key");
+            return OneShotAction::None
+        };
+        *slot = Some(OneShot { key, key_index, status: OneShotStatus::Held { interrupted: false } });
+        OneShotAction::Activate(key)
+    }
+
+    /// 松开按键，非one-shot键返回`None`
+    pub fn on_release(&mut self, key_index: usize, now: Instant, scheduler: &mut Scheduler) -> Option<OneShotAction> {
+        let action = self.release(key_index, now);
+        self.reschedule(scheduler);
+        action
+    }
+
+    fn release(&mut self, key_index: usize, now: Instant) -> Option<OneShotAction> {
+        let slot = self.slots.iter_mut().find(|slot| matches!(slot,
+            Some(OneShot { key_index: idx, status: OneShotStatus::Held { .. }, .. }) if *idx == key_index
+        ))?;
+        let one_shot = slot.as_mut().unwrap();
+        match one_shot.status {
+            OneShotStatus::Held { interrupted: true } => {
+                let key = one_shot.key;
+                *slot = None;
+                Some(OneShotAction::Deactivate(key))
+            },
+            _ => {
+                let deadline = now + Duration::from_millis(ONE_SHOT_TIMEOUT_MS);
+                one_shot.status = OneShotStatus::Armed { deadline };
+                Some(OneShotAction::None)
+            },
+        }
+    }
+
+    /// 按下其他键，按住中的one-shot键退化为普通按住
+    pub fn on_other_press(&mut self, key_index: usize) {
+        for one_shot in self.slots.iter_mut().flatten() {
+            if one_shot.key_index != key_index
+                && let OneShotStatus::Held { interrupted } = &mut one_shot.status {
+                *interrupted = true;
+            }
+        }
+    }
+
+    /// 普通键按下后调用，逐个取出需要撤销的已轻击键
+    pub fn pop_armed(&mut self, scheduler: &mut Scheduler) -> Option<StateKey> {
+        self.pop_if(scheduler, |status| matches!(status, OneShotStatus::Armed { .. }))
+    }
+
+    /// 逐个取出已超时的已轻击键
+    pub fn pop_expired(&mut self, now: Instant, scheduler: &mut Scheduler) -> Option<StateKey> {
+        self.pop_if(scheduler, |status| matches!(status, OneShotStatus::Armed { deadline } if deadline <= now))
+    }
+
+    /// 是否有已轻击或锁定、等待作用的one-shot键
+    pub fn is_armed(&self) -> bool {
+        self.slots.iter().flatten()
+            .any(|one_shot| matches!(one_shot.status, OneShotStatus::Armed { .. } | OneShotStatus::Locked))
+    }
+
+    /// 最近的超时时间
+    fn next_deadline(&self) -> Option<Instant> {
+        self.slots.iter().flatten()
+            .filter_map(|one_shot| match one_shot.status {
+                OneShotStatus::Armed { deadline } => Some(deadline),
+                _ => None,
+            })
+            .min()
+    }
+
+    fn pop_if(&mut self, scheduler: &mut Scheduler, f: impl Fn(OneShotStatus) -> bool) -> Option<StateKey> {
+        let slot = self.slots.iter_mut().find(|slot| matches!(slot, Some(one_shot) if f(one_shot.status)))?;
+        let key = slot.take().map(|one_shot| one_shot.key);
+        self.reschedule(scheduler);
+        key
+    }
+
+    /// 按最近的超时时间登记超时任务
+    fn reschedule(&self, scheduler: &mut Scheduler) {
+        scheduler.update(self.next_deadline(), Task::OneShotTimeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::kbd::key::ModifierKey;
+    use crate::core::kbd::key::basic_key::*;
+
+    const KEY: usize = 1;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    /// 在0ms轻击one-shot键
+    fn tap(one_shots: &mut OneShots, key: StateKey, key_index: usize, scheduler: &mut Scheduler) {
+        assert_eq!(one_shots.on_press(key, key_index, scheduler), OneShotAction::Activate(key));
+        assert_eq!(one_shots.on_release(key_index, at(0), scheduler), Some(OneShotAction::None));
+    }
+
+    #[test]
+    fn tap_then_consume_on_next_key() {
+        let mut one_shots = OneShots::default();
+        let mut scheduler = Scheduler::default();
+        tap(&mut one_shots, LShift.into(), KEY, &mut scheduler);
+        assert!(one_shots.is_armed());
+        assert_eq!(scheduler.next_deadline(), Some(at(ONE_SHOT_TIMEOUT_MS)));
+
+        // 普通键按下后撤销，不再超时
+        assert_eq!(one_shots.pop_armed(&mut scheduler), Some(LShift.into()));
+        assert_eq!(one_shots.pop_armed(&mut scheduler), None);
+        assert!(!one_shots.is_armed());
+        assert_eq!(scheduler.next_deadline(), None);
+    }
+
+    #[test]
+    fn armed_key_times_out() {
+        let mut one_shots = OneShots::default();
+        let mut scheduler = Scheduler::default();
+        tap(&mut one_shots, LShift.into(), KEY, &mut scheduler);
+        assert_eq!(one_shots.pop_expired(at(ONE_SHOT_TIMEOUT_MS - 1), &mut scheduler), None);
+        assert_eq!(one_shots.pop_expired(at(ONE_SHOT_TIMEOUT_MS), &mut scheduler), Some(LShift.into()));
+        assert_eq!(scheduler.next_deadline(), None);
+    }
+
+    #[test]
+    fn held_with_other_key_acts_as_normal_modifier() {
+        let mut one_shots = OneShots::default();
+        let mut scheduler = Scheduler::default();
+        assert_eq!(one_shots.on_press(LCtrl.into(), KEY, &mut scheduler), OneShotAction::Activate(LCtrl.into()));
+        one_shots.on_other_press(KEY + 1);
+        assert_eq!(one_shots.on_release(KEY, at(100), &mut scheduler), Some(OneShotAction::Deactivate(LCtrl.into())));
+        assert!(!one_shots.is_armed());
+        // 非one-shot键松开
+        assert_eq!(one_shots.on_release(KEY + 1, at(150), &mut scheduler), None);
+    }
+
+    #[test]
+    fn tap_again_to_lock() {
+        let mut one_shots = OneShots::default();
+        let mut scheduler = Scheduler::default();
+        tap(&mut one_shots, LShift.into(), KEY, &mut scheduler);
+        let action = one_shots.on_press(LShift.into(), KEY, &mut scheduler);
+        if !ONE_SHOT_TAP_TO_LOCK {
+            assert_eq!(action, OneShotAction::Deactivate(LShift.into()));
+            return
+        }
+
+        // 锁定后不随普通键撤销，也不超时
+        assert_eq!(action, OneShotAction::None);
+        assert_eq!(one_shots.on_release(KEY, at(50), &mut scheduler), None);
+        assert_eq!(one_shots.pop_armed(&mut scheduler), None);
+        assert_eq!(scheduler.next_deadline(), None);
+        assert!(one_shots.is_armed());
+        // 再次按下解除锁定
+        assert_eq!(one_shots.on_press(LShift.into(), KEY, &mut scheduler), OneShotAction::Deactivate(LShift.into()));
+        assert!(!one_shots.is_armed());
+    }
+
+    #[test]
+    fn slots_full_ignores_key() {
+        const MODIFIERS: [ModifierKey; 8] = [LCtrl, LShift, LAlt, LGui, RCtrl, RShift, RAlt, RGui];
+        let mut one_shots = OneShots::default();
+        let mut scheduler = Scheduler::default();
+        for (key_index, modifier) in MODIFIERS.into_iter().enumerate().take(ONE_SHOT_MAX) {
+            tap(&mut one_shots, modifier.into(), key_index, &mut scheduler);
+        }
+        assert_eq!(one_shots.on_press(LayerOn(1).into(), ONE_SHOT_MAX, &mut scheduler), OneShotAction::None);
+        assert_eq!(one_shots.on_release(ONE_SHOT_MAX, at(0), &mut scheduler), None);
+
+        // 全部在普通键按下后撤销
+        let popped = core::iter::from_fn(|| one_shots.pop_armed(&mut scheduler)).count();
+        assert_eq!(popped, ONE_SHOT_MAX);
+    }
+
+    #[test]
+    fn one_shot_layer() {
+        let mut one_shots = OneShots::default();
+        let mut scheduler = Scheduler::default();
+        let layer: StateKey = LayerOn(2).into();
+        // 与修饰键同时生效，先超时的先撤销
+        tap(&mut one_shots, layer, KEY, &mut scheduler);
+        assert_eq!(one_shots.on_press(LShift.into(), KEY + 1, &mut scheduler), OneShotAction::Activate(LShift.into()));
+        one_shots.on_release(KEY + 1, at(1000), &mut scheduler);
+        assert_eq!(scheduler.next_deadline(), Some(at(ONE_SHOT_TIMEOUT_MS)));
+        assert_eq!(one_shots.pop_expired(at(ONE_SHOT_TIMEOUT_MS), &mut scheduler), Some(layer));
+        assert_eq!(scheduler.next_deadline(), Some(at(1000 + ONE_SHOT_TIMEOUT_MS)));
+        assert_eq!(one_shots.pop_armed(&mut scheduler), Some(LShift.into()));
+    }
+}
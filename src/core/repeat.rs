@@ -0,0 +1,18 @@
+// 重复键(Repeat/Alt Repeat)
+// 记录上一个发送的普通键及当时的修饰键，重复键以相同修饰键再次发送，反向重复键发送配置的反向键
+
+use super::kbd::key::QwertyKey;
+use super::kbd::key_action::AltRepeat;
+
+/// 查找反向键，配置中的键对双向匹配
+pub fn find_alt(alt_repeats: &[AltRepeat], qwerty_key: QwertyKey) -> Option<QwertyKey> {
+    alt_repeats.iter().find_map(|pair| {
+        if pair.key == qwerty_key {
+            Some(pair.alt)
+        } else if pair.alt == qwerty_key {
+            Some(pair.key)
+        } else {
+            None
+        }
+    })
+}
@@ -0,0 +1,74 @@
+// 定时任务调度
+// 各功能在自己的超时时间变化时登记或取消定时任务，KbdCore在等待按键事件时同时等待最早的任务，
+// 到时后按时间顺序取出任务交给对应功能处理，早于下一个按键事件的任务先于该事件处理
+
+use embassy_time::Instant;
+use heapless::Vec;
+
+use crate::kbd_cfg::core::SCHEDULER_SIZE;
+
+/// 定时任务，同一任务同时只有一个登记时间
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    /// One-shot键超时
+    OneShotTimeout,
+    /// 自动Shift按住超过阈值
+    AutoShiftTimeout,
+    /// Leader键序列捕获超时
+    LeaderTimeout,
+    /// Caps Word空闲超时
+    CapsWordTimeout,
+    /// 执行下一条宏指令
+    MacroStep,
+    /// 鼠标键输出移动量
+    MouseKeysTick,
+    /// 待定键判定超时
+    HoldTapTimeout,
+    /// 轻击舞连击窗口超时
+    TapDanceTimeout,
+    /// 组合键判定超时
+    ComboTimeout,
+    /// 保存修改后的配置
+    SaveSettings,
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: Vec<(Instant, Task), SCHEDULER_SIZE>,
+}
+
+impl Scheduler {
+    /// 登记任务，已登记的任务更新执行时间
+    pub fn schedule(&mut self, at: Instant, task: Task) {
+        if let Some(entry) = self.tasks.iter_mut().find(|(_, t)| *t == task) {
+            entry.0 = at;
+        } else if self.tasks.push((at, task)).is_err() {
+            defmt::error!("Scheduler full, drop task");
+        }
+    }
+
+    pub fn cancel(&mut self, task: Task) {
+        self.tasks.retain(|(_, t)| *t != task);
+    }
+
+    /// 按功能当前的超时时间登记或取消任务
+    pub fn update(&mut self, at: Option<Instant>, task: Task) {
+        match at {
+            Some(at) => self.schedule(at, task),
+            None => self.cancel(task),
+        }
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.tasks.iter().map(|(at, _)| *at).min()
+    }
+
+    /// 取出最早的已到时任务
+    pub fn pop_expired(&mut self, now: Instant) -> Option<Task> {
+        let (index, _) = self.tasks.iter()
+            .enumerate()
+            .filter(|(_, (at, _))| *at <= now)
+            .min_by_key(|(_, (at, _))| *at)?;
+        Some(self.tasks.swap_remove(index).1)
+    }
+}
@@ -0,0 +1,162 @@
+// 可保存的键盘配置
+// 包括VIA修改的布局和动态宏，以及自动Shift开关、Unicode输入方式、默认层
+// 布局按VIA键码保存，不能用键码表示的动作保存为`KC_UNSUPPORTED`，加载时沿用初始布局中同一位置的动作
+// 序列化格式：各层按键的键码(u16大端)、动态宏缓冲区、自动Shift开关、Unicode输入方式、默认层
+
+use super::macros::DynamicMacros;
+use super::unicode::UnicodeMode;
+use super::via::{action_of, keycode_of, unicode_mode_of, KC_UNSUPPORTED};
+use super::KeyMap;
+use crate::kbd_cfg::core::{AUTO_SHIFT_ENABLED, DYNAMIC_MACRO_BUFFER_SIZE, UNICODE_DEFAULT_MODE};
+
+/// 保存配置失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveError {
+    /// 序列化后的配置超出存储区大小
+    TooLarge,
+    /// 写入存储失败
+    Write,
+}
+
+/// 配置的持久化存储，由外设层实现
+pub trait SettingsStore {
+    /// 保存序列化后的配置，失败时返回原因，由调用方记录和提示
+    fn save(&mut self, payload: &[u8]) -> Result<(), SaveError>;
+}
+
+pub struct Settings<const KEY_NUM: usize, const LAYER_NUM: usize> {
+    pub key_map: KeyMap<KEY_NUM, LAYER_NUM>,
+    pub dynamic_macros: DynamicMacros,
+    pub auto_shift_enabled: bool,
+    pub unicode_mode: UnicodeMode,
+    /// 通过`DefaultLayer`切换的默认层
+    pub default_layer: u8,
+}
+
+impl<const KEY_NUM: usize, const LAYER_NUM: usize> Settings<KEY_NUM, LAYER_NUM> {
+    /// 序列化后的长度
+    pub const SIZE: usize = KEY_NUM * LAYER_NUM * 2 + DYNAMIC_MACRO_BUFFER_SIZE + 3;
+
+    /// 以`key_map`为布局的默认配置
+    pub fn new(key_map: KeyMap<KEY_NUM, LAYER_NUM>) -> Self {
+        Self {
+            key_map,
+            dynamic_macros: DynamicMacros::default(),
+            auto_shift_enabled: AUTO_SHIFT_ENABLED,
+            unicode_mode: UNICODE_DEFAULT_MODE,
+            default_layer: 0,
+        }
+    }
+
+    /// 从序列化的配置恢复，长度不符或内容无效时返回`None`
+    pub fn deserialize(payload: &[u8], default_key_map: &KeyMap<KEY_NUM, LAYER_NUM>) -> Option<Self> {
+        if payload.len() != Self::SIZE {
+            defmt::warn!("Settings size mismatch: {}, expected {}", payload.len(), Self::SIZE);
+            return None
+        }
+        let (keycodes, rest) = payload.split_at(KEY_NUM * LAYER_NUM * 2);
+        let (macros, rest) = rest.split_at(DYNAMIC_MACRO_BUFFER_SIZE);
+
+        let mut settings = Self::new(*default_key_map);
+        let actions = settings.key_map.iter_mut().flatten();
+        for (action, bytes) in actions.zip(keycodes.chunks_exact(2)) {
+            let keycode = u16::from_be_bytes([bytes[0], bytes[1]]);
+            if keycode == KC_UNSUPPORTED {
+                continue
+            }
+            match action_of(keycode) {
+                Some(stored) => *action = stored,
+                None => defmt::warn!("Unsupported stored keycode `{:#x}`", keycode),
+            }
+        }
+        settings.dynamic_macros.buffer_mut().copy_from_slice(macros);
+        settings.auto_shift_enabled = rest[0] != 0;
+        settings.unicode_mode = unicode_mode_of(rest[1])?;
+        settings.default_layer = rest[2];
+        if settings.default_layer as usize >= LAYER_NUM {
+            defmt::warn!("Stored default layer `{}` out of range", settings.default_layer);
+            return None
+        }
+        Some(settings)
+    }
+}
+
+/// 序列化配置，`buf`长度不小于`Settings::SIZE`，返回序列化后的长度
+pub fn serialize<const KEY_NUM: usize, const LAYER_NUM: usize>(
+    key_map: &KeyMap<KEY_NUM, LAYER_NUM>,
+    dynamic_macros: &DynamicMacros,
+    auto_shift_enabled: bool,
+    unicode_mode: UnicodeMode,
+    default_layer: u8,
+    buf: &mut [u8],
+) -> usize {
+    let size = Settings::<KEY_NUM, LAYER_NUM>::SIZE;
+    let (keycodes, rest) = buf[..size].split_at_mut(KEY_NUM * LAYER_NUM * 2);
+    let (macros, rest) = rest.split_at_mut(DYNAMIC_MACRO_BUFFER_SIZE);
+
+    for (action, bytes) in key_map.iter().flatten().zip(keycodes.chunks_exact_mut(2)) {
+        bytes.copy_from_slice(&keycode_of(action).to_be_bytes());
+    }
+    macros.copy_from_slice(dynamic_macros.buffer());
+    rest[0] = auto_shift_enabled as u8;
+    rest[1] = unicode_mode as u8;
+    rest[2] = default_layer;
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::kbd::key::basic_key::*;
+    use crate::core::kbd::key_action::{ck, hk, sk, uc};
+    use crate::kbd_cfg::via::VIA_TAPPING_TERM_MS;
+
+    const KEY_NUM: usize = 4;
+    const LAYER_NUM: usize = 2;
+
+    fn round_trip(key_map: &KeyMap<KEY_NUM, LAYER_NUM>, default_key_map: &KeyMap<KEY_NUM, LAYER_NUM>) -> Settings<KEY_NUM, LAYER_NUM> {
+        let settings = Settings::new(*key_map);
+        let mut buf = [0; Settings::<KEY_NUM, LAYER_NUM>::SIZE];
+        let size = serialize(
+            key_map,
+            &settings.dynamic_macros,
+            settings.auto_shift_enabled,
+            settings.unicode_mode,
+            settings.default_layer,
+            &mut buf,
+        );
+        Settings::deserialize(&buf[..size], default_key_map).unwrap()
+    }
+
+    #[test]
+    fn actions_without_keycode_keep_default() {
+        let default_key_map = [
+            [sk(LShift, A), hk(LCtrl, B, 300), uc('é'), ck(C)],
+            [ck(D); KEY_NUM],
+        ];
+        // 布局与初始布局相同时原样恢复，不会变成近似的键码动作
+        let settings = round_trip(&default_key_map, &default_key_map);
+        assert!(settings.key_map == default_key_map);
+
+        // 能用键码表示的修改照常保存
+        let mut key_map = default_key_map;
+        key_map[0][3] = hk(LCtrl, E, VIA_TAPPING_TERM_MS);
+        key_map[1][0] = ck(F);
+        let settings = round_trip(&key_map, &default_key_map);
+        assert!(settings.key_map == key_map);
+    }
+
+    #[test]
+    fn default_layer_round_trip() {
+        let key_map = [[ck(A); KEY_NUM]; LAYER_NUM];
+        let mut buf = [0; Settings::<KEY_NUM, LAYER_NUM>::SIZE];
+        let size = serialize(&key_map, &DynamicMacros::default(), false, UnicodeMode::MacOS, 1, &mut buf);
+        let settings = Settings::deserialize(&buf[..size], &key_map).unwrap();
+        assert_eq!(settings.default_layer, 1);
+        assert_eq!(settings.unicode_mode, UnicodeMode::MacOS);
+
+        // 超出层数的默认层视为无效配置
+        buf[size - 1] = LAYER_NUM as u8;
+        assert!(Settings::deserialize(&buf[..size], &key_map).is_none());
+    }
+}
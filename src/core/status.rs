@@ -0,0 +1,24 @@
+// 键盘状态快照
+// 核心处理完事件后更新，供指示灯等外设查询，只保留最新状态
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// 激活层位图
+static LAYER_BITS: AtomicU32 = AtomicU32::new(1);
+/// 是否有等待作用的one-shot键
+static ONE_SHOT_ARMED: AtomicBool = AtomicBool::new(false);
+
+pub fn publish_status(layer_bits: u32, one_shot_armed: bool) {
+    LAYER_BITS.store(layer_bits, Ordering::Release);
+    ONE_SHOT_ARMED.store(one_shot_armed, Ordering::Release);
+}
+
+#[cfg(not(test))]
+pub fn layer_bits() -> u32 {
+    LAYER_BITS.load(Ordering::Acquire)
+}
+
+#[cfg(not(test))]
+pub fn one_shot_armed() -> bool {
+    ONE_SHOT_ARMED.load(Ordering::Acquire)
+}
@@ -0,0 +1,183 @@
+// 轻击舞(Tap Dance)判定逻辑
+// 与IO无关，只根据按键事件和时间推进状态，方便单独测试
+
+use embassy_time::{Duration, Instant};
+
+use super::kbd::key::KbdKey;
+use super::kbd::key_action::TapDance;
+use super::kbd::key_event::KeyEvent;
+use super::scheduler::{Scheduler, Task};
+
+/// 判定结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapDanceDecision {
+    /// 还未确定，继续等待
+    Pending,
+    /// 已确定触发的键，`None`表示该连击次数没有配置对应的键
+    Resolved(Option<KbdKey>),
+}
+
+/// 一次轻击舞的判定状态
+#[derive(Debug, Clone, Copy)]
+pub struct TapDanceState {
+    /// `KeyMapTables::tap_dances`的索引
+    pub index: u8,
+    /// 触发轻击舞的按键
+    pub key_index: usize,
+    /// 已连击次数
+    pub tap_count: usize,
+    /// 该按键当前是否按住
+    pub is_pressed: bool,
+    /// 连击窗口截止时间
+    pub deadline: Instant,
+}
+
+impl TapDanceState {
+    /// 按键第一次按下时创建，登记连击窗口的超时任务
+    pub fn new(index: u8, key_index: usize, tap_dance: &TapDance, now: Instant, scheduler: &mut Scheduler) -> Self {
+        let deadline = now + Duration::from_millis(tap_dance.tapping_term_ms as u64);
+        scheduler.schedule(deadline, Task::TapDanceTimeout);
+        Self { index, key_index, tap_count: 1, is_pressed: true, deadline }
+    }
+
+    /// 处理判定过程中收到的按键事件，连击窗口重新计时时更新超时任务，判定完成时取消
+    ///
+    /// 其他键的事件会立即结束判定，调用方需在处理结果后继续处理该事件
+    pub fn on_event(&mut self, tap_dance: &TapDance, event: &KeyEvent, scheduler: &mut Scheduler) -> TapDanceDecision {
+        let decision = self.decide(tap_dance, event);
+        match decision {
+            TapDanceDecision::Pending => scheduler.schedule(self.deadline, Task::TapDanceTimeout),
+            TapDanceDecision::Resolved(_) => scheduler.cancel(Task::TapDanceTimeout),
+        }
+        decision
+    }
+
+    fn decide(&mut self, tap_dance: &TapDance, event: &KeyEvent) -> TapDanceDecision {
+        if event.key_index as usize != self.key_index {
+            return TapDanceDecision::Resolved(self.resolve(tap_dance));
+        }
+
+        self.deadline = event.time + Duration::from_millis(tap_dance.tapping_term_ms as u64);
+        self.is_pressed = event.is_pressed;
+        if event.is_pressed {
+            self.tap_count = (self.tap_count+1).min(tap_dance.steps.len());
+        }
+
+        // 已到达最大连击次数，后续不会再有变化
+        let max_taps = tap_dance.max_taps();
+        if self.tap_count >= max_taps {
+            let (_, hold) = tap_dance.steps[self.tap_count-1];
+            if !self.is_pressed || hold.is_none() {
+                return TapDanceDecision::Resolved(self.resolve(tap_dance));
+            }
+        }
+        TapDanceDecision::Pending
+    }
+
+    /// 连击窗口超时
+    pub fn on_timeout(&self, tap_dance: &TapDance) -> TapDanceDecision {
+        TapDanceDecision::Resolved(self.resolve(tap_dance))
+    }
+
+    /// 按住时优先触发按住键，没有配置则退化为轻击键
+    fn resolve(&self, tap_dance: &TapDance) -> Option<KbdKey> {
+        let (tap, hold) = tap_dance.steps[self.tap_count-1];
+        if self.is_pressed {
+            hold.or(tap)
+        } else {
+            tap
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::kbd::key::basic_key::*;
+
+    fn tap_dance() -> TapDance {
+        TapDance::new(200)
+            .tap(1, A).hold(1, LShift)
+            .tap(2, B)
+    }
+
+    fn event(is_pressed: bool, key_index: u8, ms: u64) -> KeyEvent {
+        KeyEvent::new(is_pressed, key_index, Instant::from_millis(ms))
+    }
+
+    fn start(tap_dance: &TapDance, scheduler: &mut Scheduler) -> TapDanceState {
+        TapDanceState::new(0, 1, tap_dance, Instant::from_millis(0), scheduler)
+    }
+
+    #[test]
+    fn single_tap_resolves_on_timeout() {
+        let td = tap_dance();
+        let mut scheduler = Scheduler::default();
+        let mut state = start(&td, &mut scheduler);
+        assert_eq!(state.on_event(&td, &event(false, 1, 50), &mut scheduler), TapDanceDecision::Pending);
+        // 每次按下/松开重新计时
+        assert_eq!(state.deadline, Instant::from_millis(250));
+        assert_eq!(scheduler.next_deadline(), Some(state.deadline));
+        assert_eq!(state.on_timeout(&td), TapDanceDecision::Resolved(Some(A.into())));
+    }
+
+    #[test]
+    fn max_taps_resolves_immediately() {
+        let td = tap_dance();
+        let mut scheduler = Scheduler::default();
+        let mut state = start(&td, &mut scheduler);
+        assert_eq!(state.on_event(&td, &event(false, 1, 50), &mut scheduler), TapDanceDecision::Pending);
+        // 最后一次没有配置按住键，按下即判定
+        assert_eq!(state.on_event(&td, &event(true, 1, 100), &mut scheduler), TapDanceDecision::Resolved(Some(B.into())));
+        assert_eq!(state.tap_count, 2);
+        assert!(state.is_pressed);
+        assert_eq!(scheduler.next_deadline(), None);
+
+        // 配置了按住键时等到松开
+        let td = td.hold(2, LCtrl);
+        let mut state = start(&td, &mut scheduler);
+        state.on_event(&td, &event(false, 1, 50), &mut scheduler);
+        assert_eq!(state.on_event(&td, &event(true, 1, 100), &mut scheduler), TapDanceDecision::Pending);
+        assert_eq!(state.on_event(&td, &event(false, 1, 150), &mut scheduler), TapDanceDecision::Resolved(Some(B.into())));
+    }
+
+    #[test]
+    fn hold_per_tap_count() {
+        let td = tap_dance();
+        let mut scheduler = Scheduler::default();
+        // 第1次按住触发按住键
+        let state = start(&td, &mut scheduler);
+        assert_eq!(state.on_timeout(&td), TapDanceDecision::Resolved(Some(LShift.into())));
+
+        // 第2次没有配置按住键，退化为轻击键
+        let mut state = start(&td, &mut scheduler);
+        state.on_event(&td, &event(false, 1, 50), &mut scheduler);
+        let td = td.hold(3, LCtrl);
+        assert_eq!(state.on_event(&td, &event(true, 1, 100), &mut scheduler), TapDanceDecision::Pending);
+        assert_eq!(state.on_timeout(&td), TapDanceDecision::Resolved(Some(B.into())));
+    }
+
+    #[test]
+    fn unconfigured_count_resolves_to_none() {
+        let td = TapDance::new(200).tap(2, B);
+        let mut scheduler = Scheduler::default();
+        let mut state = start(&td, &mut scheduler);
+        assert_eq!(state.on_event(&td, &event(false, 1, 50), &mut scheduler), TapDanceDecision::Pending);
+        assert_eq!(state.on_timeout(&td), TapDanceDecision::Resolved(None));
+    }
+
+    #[test]
+    fn interrupted_by_other_key() {
+        let td = tap_dance();
+        let mut scheduler = Scheduler::default();
+        let mut state = start(&td, &mut scheduler);
+        state.on_event(&td, &event(false, 1, 50), &mut scheduler);
+        // 其他键按下立即按当前次数判定
+        assert_eq!(state.on_event(&td, &event(true, 2, 80), &mut scheduler), TapDanceDecision::Resolved(Some(A.into())));
+
+        // 按住期间被打断，判定为按住
+        let mut state = start(&td, &mut scheduler);
+        assert_eq!(state.on_event(&td, &event(true, 2, 80), &mut scheduler), TapDanceDecision::Resolved(Some(LShift.into())));
+        assert!(state.is_pressed);
+    }
+}
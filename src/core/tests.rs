@@ -0,0 +1,296 @@
+// KbdCore的集成测试
+// 直接向事件队列输入带时间的按键事件，收集发送给主机的键盘报文，检查各功能组合后的输出
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::vec::Vec;
+
+use embassy_futures::block_on;
+use embassy_futures::select::select;
+
+use super::*;
+use kbd::key_action::*;
+use kbd::key::basic_key::*;
+use kbd_report::{KbdReport, NKRO_BITMAP_SIZE};
+use settings::SaveError;
+use crate::kbd_cfg::core::EVENT_QUEUE_SIZE;
+
+const KEY_NUM: usize = 8;
+const LAYER_NUM: usize = 2;
+
+/// 核心使用全局的通道，测试需依次运行
+static CORE_LOCK: Mutex<()> = Mutex::new(());
+
+/// 报文中的modifier和按下的键码
+type Report = (u8, Vec<u8>);
+
+fn report(modifier: u8, keys: &[QwertyKey]) -> Report {
+    (modifier, keys.iter().map(|&key| key as u8).collect())
+}
+
+/// 依次轻击`keys`产生的报文
+fn taps(modifier: u8, keys: &[QwertyKey]) -> Vec<Report> {
+    keys.iter().flat_map(|&key| [report(modifier, &[key]), report(modifier, &[])]).collect()
+}
+
+struct NullStore;
+
+impl SettingsStore for NullStore {
+    fn save(&mut self, _payload: &[u8]) -> Result<(), SaveError> {
+        Ok(())
+    }
+}
+
+struct TestCore {
+    core: KbdCore<KEY_NUM, LAYER_NUM>,
+    reports: Vec<KbdReport>,
+    /// 持有到测试结束，避免与其他测试同时使用通道
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TestCore {
+    fn new(key_map: KeyMap<KEY_NUM, LAYER_NUM>, tables: KeyMapTables) -> Self {
+        let lock = CORE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        // 丢弃之前失败的测试留下的报文
+        while KEYBOARD_REPORT_CHANNEL.try_receive().is_ok() {}
+        let store: &'static mut dyn SettingsStore = Box::leak(Box::new(NullStore));
+        let settings_buf = Box::leak(Box::new([0; SETTINGS_BUFFER_SIZE]));
+        let core = KbdCore::new(Settings::new(key_map), key_map, tables, store, settings_buf);
+        Self { core, reports: Vec::new(), _lock: lock }
+    }
+
+    fn press(&mut self, key_index: u8, ms: u64) {
+        self.event(true, key_index, ms);
+    }
+
+    fn release(&mut self, key_index: u8, ms: u64) {
+        self.event(false, key_index, ms);
+    }
+
+    fn event(&mut self, is_pressed: bool, key_index: u8, ms: u64) {
+        self.core.event_queue.push_back(KeyEvent::new(is_pressed, key_index, Instant::from_millis(ms)));
+        self.run(Instant::from_millis(ms));
+    }
+
+    /// 处理所有已到时的任务和队列中的事件，`now`为没有事件时任务的判定时间
+    fn run(&mut self, now: Instant) {
+        let Self { core, reports, .. } = self;
+        block_on(async {
+            let process = async {
+                while let Some(event) = core.poll_event(now).await {
+                    core.dispatch(event).await;
+                }
+            };
+            // 边处理边接收，避免通道写满后阻塞
+            let receive = async {
+                loop {
+                    reports.push(KEYBOARD_REPORT_CHANNEL.receive().await);
+                }
+            };
+            select(process, receive).await;
+        });
+        while let Ok(report) = KEYBOARD_REPORT_CHANNEL.try_receive() {
+            self.reports.push(report);
+        }
+    }
+
+    /// 时钟走到所有定时任务都到时(如播放完宏)
+    fn flush(&mut self) {
+        self.run(Instant::MAX);
+    }
+
+    /// 取出之后发送的报文
+    fn take_reports(&mut self) -> Vec<Report> {
+        self.reports.drain(..).map(|report| match report {
+            KbdReport::Nkro { modifier, bitmap } => {
+                let keys = (0..NKRO_BITMAP_SIZE * 8).filter(|&code| bitmap[code / 8] & (1 << (code % 8)) != 0);
+                (modifier, keys.map(|code| code as u8).collect())
+            },
+            KbdReport::Boot { .. } => unreachable!(),
+        }).collect()
+    }
+}
+
+const SHIFT: u8 = LShift.bit();
+const CTRL: u8 = LCtrl.bit();
+
+/// 按住Shift时输入Unicode字符，输入序列中不包含用户按住的修饰键，输入完成后恢复
+#[test]
+fn unicode_masks_held_modifiers() {
+    let mut key_map = [[NA; KEY_NUM]; LAYER_NUM];
+    key_map[0][0] = ck(LShift);
+    key_map[0][1] = uc('é');
+    let mut core = TestCore::new(key_map, KeyMapTables::default());
+
+    let cases: [(UnicodeMode, &[Report]); 4] = [
+        (UnicodeMode::Linux, &[
+            &[report(CTRL, &[]), report(CTRL | SHIFT, &[])][..],
+            &taps(CTRL | SHIFT, &[U]),
+            &[report(CTRL, &[]), report(0, &[])],
+            &taps(0, &[E, Kc9, Space]),
+        ].concat()),
+        (UnicodeMode::WinAlt, &[
+            &[report(LAlt.bit(), &[])][..],
+            &taps(LAlt.bit(), &[KpPlus, E, Kp9]),
+            &[report(0, &[])],
+        ].concat()),
+        (UnicodeMode::WinCompose, &[
+            &[report(RAlt.bit(), &[]), report(0, &[])][..],
+            &taps(0, &[U, E, Kc9, Enter]),
+        ].concat()),
+        (UnicodeMode::MacOS, &[
+            &[report(LAlt.bit(), &[])][..],
+            &taps(LAlt.bit(), &[Kc0, Kc0, E, Kc9]),
+            &[report(0, &[])],
+        ].concat()),
+    ];
+    for (mode, expected) in cases {
+        core.core.unicode_mode = mode;
+        core.press(0, 0);
+        assert_eq!(core.take_reports(), [report(SHIFT, &[])]);
+        core.press(1, 10);
+        core.release(1, 20);
+        core.flush();
+        let mut expected = expected.to_vec();
+        expected.push(report(SHIFT, &[]));
+        assert_eq!(core.take_reports(), expected, "{:?}", mode);
+        core.release(0, 30);
+        assert_eq!(core.take_reports(), [report(0, &[])]);
+    }
+}
+
+/// 按键覆盖期间按下其他键，先松开替换键再恢复原修饰键
+#[test]
+fn key_override_released_before_other_key() {
+    static KEY_OVERRIDES: [KeyOverride; 1] = [KeyOverride::new(mods(&[LShift]), Backspace, Delete, 0)];
+    let mut key_map = [[NA; KEY_NUM]; LAYER_NUM];
+    key_map[0][0] = ck(LShift);
+    key_map[0][1] = ck(Backspace);
+    key_map[0][2] = ck(A);
+    let mut core = TestCore::new(key_map, KeyMapTables { key_overrides: &KEY_OVERRIDES, ..KeyMapTables::default() });
+
+    core.press(0, 0);
+    core.press(1, 10);
+    core.press(2, 20);
+    core.release(1, 30);
+    core.release(2, 40);
+    core.release(0, 50);
+    let reports = core.take_reports();
+    assert!(!reports.iter().any(|(modifier, keys)| modifier & SHIFT != 0 && keys.contains(&(Delete as u8))), "{:?}", reports);
+    assert_eq!(reports, [
+        report(SHIFT, &[]),
+        report(0, &[Delete]),
+        report(0, &[]),
+        report(SHIFT, &[A]),
+        report(SHIFT, &[]),
+        report(0, &[]),
+    ]);
+}
+
+/// 重复键按住期间按下其他键，先以记录的modifier松开重复的键再恢复原修饰键
+#[test]
+fn repeat_released_before_other_key() {
+    let mut key_map = [[NA; KEY_NUM]; LAYER_NUM];
+    key_map[0][0] = ck(LCtrl);
+    key_map[0][1] = ck(LShift);
+    key_map[0][2] = ck(A);
+    key_map[0][3] = KeyAction::Repeat;
+    key_map[0][4] = ck(B);
+    let mut core = TestCore::new(key_map, KeyMapTables::default());
+
+    core.press(0, 0);
+    core.press(2, 10);
+    core.release(2, 20);
+    core.release(0, 30);
+    core.take_reports();
+    core.press(1, 40);
+    core.press(3, 50);
+    core.press(4, 60);
+    core.release(3, 70);
+    core.release(4, 80);
+    core.release(1, 90);
+    let reports = core.take_reports();
+    assert!(!reports.iter().any(|(modifier, keys)| modifier & SHIFT != 0 && keys.contains(&(A as u8))), "{:?}", reports);
+    assert_eq!(reports, [
+        report(SHIFT, &[]),
+        report(CTRL, &[A]),
+        report(CTRL, &[]),
+        report(SHIFT, &[B]),
+        report(SHIFT, &[]),
+        report(0, &[]),
+    ]);
+}
+
+/// 同时按住两个home row mod再轻击其他键，两个修饰键按按下顺序生效
+#[test]
+fn two_home_row_mods_with_tap() {
+    let hold_taps: &'static [HoldTap] = Box::leak(Box::new([
+        HoldTap::new(LCtrl, A, 200).flavor(HoldTapFlavor::Balanced),
+        HoldTap::new(LShift, S, 200).flavor(HoldTapFlavor::Balanced),
+    ]));
+    let mut key_map = [[NA; KEY_NUM]; LAYER_NUM];
+    key_map[0][0] = ht(0);
+    key_map[0][1] = ht(1);
+    key_map[0][2] = ck(J);
+    let mut core = TestCore::new(key_map, KeyMapTables { hold_taps, ..KeyMapTables::default() });
+
+    core.press(0, 0);
+    core.press(1, 10);
+    core.press(2, 20);
+    assert_eq!(core.take_reports(), []);
+    core.release(2, 30);
+    core.release(0, 40);
+    core.release(1, 50);
+    assert_eq!(core.take_reports(), [
+        report(CTRL, &[]),
+        report(CTRL | SHIFT, &[]),
+        report(CTRL | SHIFT, &[J]),
+        report(CTRL | SHIFT, &[]),
+        report(SHIFT, &[]),
+        report(0, &[]),
+    ]);
+}
+
+/// 没有待定判定时队列写满，事件按顺序正常处理
+#[test]
+fn full_queue_without_pending_key() {
+    let mut key_map = [[NA; KEY_NUM]; LAYER_NUM];
+    key_map[0][0] = ck(A);
+    let mut core = TestCore::new(key_map, KeyMapTables::default());
+
+    for i in 0..EVENT_QUEUE_SIZE as u64 {
+        core.core.event_queue.push_back(KeyEvent::new(i % 2 == 0, 0, Instant::from_millis(i * 10)));
+    }
+    assert!(core.core.event_queue.is_full());
+    core.flush();
+    assert_eq!(core.take_reports(), taps(0, &[A; EVENT_QUEUE_SIZE / 2]));
+
+    // 暂存的事件占满队列但没有待定判定时，直接重放
+    for i in 0..EVENT_QUEUE_SIZE as u64 {
+        core.core.event_queue.hold(KeyEvent::new(i % 2 == 0, 0, Instant::from_millis(1000 + i * 10)));
+    }
+    core.flush();
+    assert_eq!(core.take_reports(), taps(0, &[A; EVENT_QUEUE_SIZE / 2]));
+}
+
+/// VIA重置配置后恢复默认层，之后的按键使用第0层
+#[test]
+fn via_eeprom_reset_restores_default_layer() {
+    let mut key_map = [[NA; KEY_NUM]; LAYER_NUM];
+    key_map[0][0] = ld(1);
+    key_map[0][1] = ck(A);
+    key_map[1][1] = ck(B);
+    let mut core = TestCore::new(key_map, KeyMapTables::default());
+
+    core.press(0, 0);
+    core.release(0, 10);
+    assert_eq!(core.core.layer_state.default_layer(), 1);
+
+    let mut packet = [0; via::VIA_PACKET_SIZE];
+    // ID_EEPROM_RESET
+    packet[0] = 0x0A;
+    core.core.process_via(&mut packet);
+    assert_eq!(core.core.layer_state.default_layer(), 0);
+    core.press(1, 20);
+    core.release(1, 30);
+    assert_eq!(core.take_reports(), taps(0, &[A]));
+}
@@ -0,0 +1,168 @@
+// Unicode输入
+// 根据操作系统的输入方式，将码点转换为按键指令序列，交给宏播放器执行
+
+use heapless::Vec;
+
+use super::kbd::key::{KbdKey, ModifierKey, QwertyKey};
+use super::macros::MacroCommand;
+
+/// 单个字符展开后的最大指令数
+pub const UNICODE_COMMANDS_MAX: usize = 24;
+
+/// 操作系统的Unicode输入方式
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum UnicodeMode {
+    /// Linux IBus，Ctrl+Shift+U，输入十六进制码点后按空格
+    Linux,
+    /// Windows Alt+小键盘+号，需要在注册表中开启`EnableHexNumpad`，仅支持BMP字符
+    WinAlt,
+    /// Windows WinCompose，以右Alt为Compose键
+    WinCompose,
+    /// macOS Unicode Hex Input输入法，按住Option输入UTF-16十六进制编码
+    MacOS,
+}
+
+/// 生成输入字符`c`的指令序列
+pub fn input_commands(mode: UnicodeMode, c: char) -> Commands {
+    let mut commands = Commands::new();
+    let code = c as u32;
+
+    match mode {
+        UnicodeMode::Linux => {
+            press(&mut commands, ModifierKey::LCtrl);
+            press(&mut commands, ModifierKey::LShift);
+            tap(&mut commands, QwertyKey::U);
+            release(&mut commands, ModifierKey::LShift);
+            release(&mut commands, ModifierKey::LCtrl);
+            for digit in hex_digits(code) {
+                tap(&mut commands, hex_key(digit, false));
+            }
+            tap(&mut commands, QwertyKey::Space);
+        },
+        UnicodeMode::WinAlt if code > 0xFFFF => {
+            defmt::warn!("WinAlt only supports BMP characters, skip `{:#x}`", code);
+        },
+        UnicodeMode::WinAlt => {
+            press(&mut commands, ModifierKey::LAlt);
+            tap(&mut commands, QwertyKey::KpPlus);
+            for digit in hex_digits(code) {
+                tap(&mut commands, hex_key(digit, true));
+            }
+            release(&mut commands, ModifierKey::LAlt);
+        },
+        UnicodeMode::WinCompose => {
+            tap(&mut commands, ModifierKey::RAlt);
+            tap(&mut commands, QwertyKey::U);
+            for digit in hex_digits(code) {
+                tap(&mut commands, hex_key(digit, false));
+            }
+            tap(&mut commands, QwertyKey::Enter);
+        },
+        UnicodeMode::MacOS => {
+            press(&mut commands, ModifierKey::LAlt);
+            let mut utf16 = [0; 2];
+            for &unit in c.encode_utf16(&mut utf16).iter() {
+                for shift in [12, 8, 4, 0] {
+                    tap(&mut commands, hex_key(((unit >> shift) & 0xF) as u8, false));
+                }
+            }
+            release(&mut commands, ModifierKey::LAlt);
+        },
+    }
+    commands
+}
+
+pub type Commands = Vec<MacroCommand, UNICODE_COMMANDS_MAX>;
+
+fn press<K: Into<KbdKey>>(commands: &mut Commands, key: K) {
+    let _ = commands.push(MacroCommand::Press(key.into()));
+}
+
+fn release<K: Into<KbdKey>>(commands: &mut Commands, key: K) {
+    let _ = commands.push(MacroCommand::Release(key.into()));
+}
+
+fn tap<K: Into<KbdKey> + Copy>(commands: &mut Commands, key: K) {
+    press(commands, key);
+    release(commands, key);
+}
+
+/// 码点的十六进制数字，省略前导0
+fn hex_digits(code: u32) -> impl Iterator<Item = u8> {
+    let digit_num = (8 - code.leading_zeros() / 4).max(1);
+    (0..digit_num).rev().map(move |idx| ((code >> (idx * 4)) & 0xF) as u8)
+}
+
+/// 十六进制数字对应的按键，`keypad`表示数字使用小键盘输入
+fn hex_key(digit: u8, keypad: bool) -> QwertyKey {
+    use QwertyKey::*;
+    const DIGITS: [QwertyKey; 10] = [Kc0, Kc1, Kc2, Kc3, Kc4, Kc5, Kc6, Kc7, Kc8, Kc9];
+    const KP_DIGITS: [QwertyKey; 10] = [Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9];
+    const LETTERS: [QwertyKey; 6] = [A, B, C, D, E, F];
+
+    match digit {
+        0..=9 if keypad => KP_DIGITS[digit as usize],
+        0..=9 => DIGITS[digit as usize],
+        _ => LETTERS[(digit - 10) as usize],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MacroCommand::{Press, Release};
+    use ModifierKey::*;
+    use QwertyKey::*;
+
+    /// 轻击一组按键
+    fn taps(keys: &[QwertyKey]) -> std::vec::Vec<MacroCommand> {
+        keys.iter().flat_map(|&key| [Press(key.into()), Release(key.into())]).collect()
+    }
+
+    fn sequence(parts: &[&[MacroCommand]]) -> std::vec::Vec<MacroCommand> {
+        parts.concat()
+    }
+
+    #[test]
+    fn linux() {
+        let expected = sequence(&[
+            &[Press(LCtrl.into()), Press(LShift.into()), Press(U.into()), Release(U.into())],
+            &[Release(LShift.into()), Release(LCtrl.into())],
+            &taps(&[E, Kc9, Space]),
+        ]);
+        assert_eq!(input_commands(UnicodeMode::Linux, 'é'), expected.as_slice());
+    }
+
+    #[test]
+    fn win_alt() {
+        let expected = sequence(&[
+            &[Press(LAlt.into())],
+            &taps(&[KpPlus, Kp2, Kp0, Kp1, Kp0]),
+            &[Release(LAlt.into())],
+        ]);
+        assert_eq!(input_commands(UnicodeMode::WinAlt, '\u{2010}'), expected.as_slice());
+        // 不支持BMP以外的字符
+        assert!(input_commands(UnicodeMode::WinAlt, '😀').is_empty());
+    }
+
+    #[test]
+    fn win_compose() {
+        let expected = sequence(&[
+            &[Press(RAlt.into()), Release(RAlt.into())],
+            &taps(&[U, Kc1, F, Kc6, Kc0, Kc0, Enter]),
+        ]);
+        assert_eq!(input_commands(UnicodeMode::WinCompose, '😀'), expected.as_slice());
+    }
+
+    #[test]
+    fn mac_os() {
+        // UTF-16代理对，每个码元固定4位
+        let expected = sequence(&[
+            &[Press(LAlt.into())],
+            &taps(&[D, Kc8, Kc3, D, D, E, Kc0, Kc0]),
+            &[Release(LAlt.into())],
+        ]);
+        assert_eq!(input_commands(UnicodeMode::MacOS, '😀'), expected.as_slice());
+    }
+}
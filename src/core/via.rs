@@ -0,0 +1,430 @@
+// VIA配置协议
+// 上位机通过Raw HID发送32字节的命令报文，核心处理后在原报文上写入应答
+// 按键动作与QMK的16位键码互相转换，不能用键码表示的动作读取时返回`KC_UNSUPPORTED`，
+// 写回该键码时保持原动作不变，避免上位机导入/导出布局时丢失配置
+
+use embassy_time::Instant;
+
+use super::auto_shift::AutoShift;
+use super::kbd::key::{KbdKey, LayerKey, ModifierKey, QwertyKey, StateKey};
+use super::kbd::key_action::{KeyAction, UncertKey};
+use super::layer_state::LayerState;
+use super::macros::DynamicMacros;
+use super::unicode::UnicodeMode;
+use super::KeyMap;
+use crate::kbd_cfg::core::{AUTO_SHIFT_ENABLED, DYNAMIC_MACRO_COUNT, UNICODE_DEFAULT_MODE};
+use crate::kbd_cfg::via::{VIA_FIRMWARE_VERSION, VIA_MATRIX_COLS, VIA_TAPPING_TERM_MS};
+
+pub const VIA_PACKET_SIZE: usize = 32;
+pub type ViaPacket = [u8; VIA_PACKET_SIZE];
+
+const VIA_PROTOCOL_VERSION: u16 = 0x000C;
+
+// 命令ID
+const ID_GET_PROTOCOL_VERSION: u8 = 0x01;
+const ID_GET_KEYBOARD_VALUE: u8 = 0x02;
+const ID_SET_KEYBOARD_VALUE: u8 = 0x03;
+const ID_DYNAMIC_KEYMAP_GET_KEYCODE: u8 = 0x04;
+const ID_DYNAMIC_KEYMAP_SET_KEYCODE: u8 = 0x05;
+const ID_DYNAMIC_KEYMAP_RESET: u8 = 0x06;
+const ID_CUSTOM_SET_VALUE: u8 = 0x07;
+const ID_CUSTOM_GET_VALUE: u8 = 0x08;
+const ID_CUSTOM_SAVE: u8 = 0x09;
+const ID_EEPROM_RESET: u8 = 0x0A;
+const ID_DYNAMIC_KEYMAP_MACRO_GET_COUNT: u8 = 0x0C;
+const ID_DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE: u8 = 0x0D;
+const ID_DYNAMIC_KEYMAP_MACRO_GET_BUFFER: u8 = 0x0E;
+const ID_DYNAMIC_KEYMAP_MACRO_SET_BUFFER: u8 = 0x0F;
+const ID_DYNAMIC_KEYMAP_MACRO_RESET: u8 = 0x10;
+const ID_DYNAMIC_KEYMAP_GET_LAYER_COUNT: u8 = 0x11;
+const ID_DYNAMIC_KEYMAP_GET_BUFFER: u8 = 0x12;
+const ID_DYNAMIC_KEYMAP_SET_BUFFER: u8 = 0x13;
+const ID_UNHANDLED: u8 = 0xFF;
+
+// `ID_GET/SET_KEYBOARD_VALUE`的值ID
+const ID_UPTIME: u8 = 0x01;
+const ID_LAYOUT_OPTIONS: u8 = 0x02;
+const ID_FIRMWARE_VERSION: u8 = 0x04;
+
+// 自定义值，仅支持通道0(键盘自定义)
+const ID_CUSTOM_CHANNEL: u8 = 0x00;
+const ID_CUSTOM_AUTO_SHIFT: u8 = 0x01;
+const ID_CUSTOM_UNICODE_MODE: u8 = 0x02;
+
+// QMK键码
+const KC_NO: u16 = 0x0000;
+const KC_TRANSPARENT: u16 = 0x0001;
+const QK_MOD_TAP: u16 = 0x2000;
+const QK_LAYER_TAP: u16 = 0x4000;
+const QK_TO: u16 = 0x5200;
+const QK_MOMENTARY: u16 = 0x5220;
+const QK_DEF_LAYER: u16 = 0x5240;
+const QK_TOGGLE_LAYER: u16 = 0x5260;
+const QK_ONE_SHOT_LAYER: u16 = 0x5280;
+const QK_ONE_SHOT_MOD: u16 = 0x52A0;
+const QK_TAP_DANCE: u16 = 0x5700;
+const QK_MACRO: u16 = 0x7700;
+const QK_AUTO_SHIFT_TOGGLE: u16 = 0x7C15;
+const QK_LEADER: u16 = 0x7C58;
+const QK_CAPS_WORD_TOGGLE: u16 = 0x7C73;
+const QK_REPEAT_KEY: u16 = 0x7C79;
+const QK_ALT_REPEAT_KEY: u16 = 0x7C7A;
+/// 键盘自定义键码，用于`KeyMapTables::macros`中的静态宏
+const QK_KB: u16 = 0x7E00;
+/// 不能用键码表示的按键动作
+pub const KC_UNSUPPORTED: u16 = 0x7FFF;
+
+/// VIA中的矩阵行数，最后一行可能不满
+pub const fn matrix_rows(key_num: usize) -> usize {
+    key_num.div_ceil(VIA_MATRIX_COLS)
+}
+
+/// 修饰键对应的QMK 5位修饰键编码，低4位依次为Ctrl、Shift、Alt、Gui，第5位表示右侧
+fn mod_bits(modifier_key: ModifierKey) -> u16 {
+    let code = modifier_key as u8 - ModifierKey::LCtrl as u8;
+    (1 << (code & 0x03)) | if code >= 4 { 0x10 } else { 0 }
+}
+
+/// QMK 5位修饰键编码对应的修饰键，只支持单个修饰键
+fn modifier_of(bits: u16) -> Option<ModifierKey> {
+    let side = bits & 0x0F;
+    if side.count_ones() != 1 {
+        return None
+    }
+    let code = side.trailing_zeros() as u8 + if bits & 0x10 != 0 { 4 } else { 0 };
+    ModifierKey::from_code(ModifierKey::LCtrl as u8 + code)
+}
+
+fn state_keycode(state_key: StateKey) -> Option<u16> {
+    let code = match state_key {
+        StateKey::Modifier(modifier_key) => modifier_key as u16,
+        StateKey::Layer(LayerKey::LayerTo(layer)) if layer < 32 => QK_TO | layer as u16,
+        StateKey::Layer(LayerKey::LayerOn(layer)) if layer < 32 => QK_MOMENTARY | layer as u16,
+        StateKey::Layer(LayerKey::DefaultLayer(layer)) if layer < 32 => QK_DEF_LAYER | layer as u16,
+        StateKey::Layer(LayerKey::LayerSwitch(layer)) if layer < 32 => QK_TOGGLE_LAYER | layer as u16,
+        StateKey::Layer(_) => return None,
+    };
+    Some(code)
+}
+
+fn tap_hold_keycode(state_key: StateKey, qwerty_key: QwertyKey) -> Option<u16> {
+    let code = match state_key {
+        StateKey::Modifier(modifier_key) => QK_MOD_TAP | mod_bits(modifier_key) << 8,
+        StateKey::Layer(LayerKey::LayerOn(layer)) if layer < 16 => QK_LAYER_TAP | (layer as u16) << 8,
+        StateKey::Layer(_) => return None,
+    };
+    Some(code | qwerty_key as u16)
+}
+
+/// 按键动作对应的QMK键码
+///
+/// 键码转换回的动作与原动作不同时(如`sk`、时间不是`VIA_TAPPING_TERM_MS`的`hk`)返回`KC_UNSUPPORTED`，
+/// 否则写回读到的键码会改变原动作
+pub fn keycode_of(action: &KeyAction) -> u16 {
+    let code = match *action {
+        KeyAction::NA => Some(KC_NO),
+        KeyAction::TS => Some(KC_TRANSPARENT),
+        KeyAction::CK(KbdKey::Normal(qwerty_key)) => Some(qwerty_key as u16),
+        KeyAction::CK(KbdKey::State(state_key)) => state_keycode(state_key),
+        KeyAction::CK(KbdKey::Consumer(_)) => None,
+        KeyAction::UK(UncertKey::SK(state_key, qwerty_key) | UncertKey::HK(state_key, qwerty_key, _)) => {
+            tap_hold_keycode(state_key, qwerty_key)
+        },
+        KeyAction::UK(UncertKey::HT(_)) => None,
+        KeyAction::TD(index) => Some(QK_TAP_DANCE | index as u16),
+        KeyAction::OS(StateKey::Modifier(modifier_key)) => Some(QK_ONE_SHOT_MOD | mod_bits(modifier_key)),
+        KeyAction::OS(StateKey::Layer(LayerKey::LayerOn(layer))) if layer < 32 => Some(QK_ONE_SHOT_LAYER | layer as u16),
+        KeyAction::OS(_) => None,
+        KeyAction::Macro(index) if index < 64 => Some(QK_KB + index as u16),
+        KeyAction::DynMacro(index) if index < 128 => Some(QK_MACRO + index as u16),
+        KeyAction::AutoShiftToggle => Some(QK_AUTO_SHIFT_TOGGLE),
+        KeyAction::Leader => Some(QK_LEADER),
+        KeyAction::CapsWord => Some(QK_CAPS_WORD_TOGGLE),
+        KeyAction::Repeat => Some(QK_REPEAT_KEY),
+        KeyAction::AltRepeat => Some(QK_ALT_REPEAT_KEY),
+        KeyAction::Macro(_) | KeyAction::DynMacro(_) | KeyAction::Unicode(_) | KeyAction::UnicodeMode(_) => None,
+    };
+    code.filter(|&code| action_of(code) == Some(*action)).unwrap_or(KC_UNSUPPORTED)
+}
+
+/// QMK键码对应的按键动作，不支持的键码返回`None`
+pub fn action_of(keycode: u16) -> Option<KeyAction> {
+    let low = (keycode & 0xFF) as u8;
+    let layer = (keycode & 0x1F) as u8;
+    let action = match keycode {
+        KC_NO => KeyAction::NA,
+        KC_TRANSPARENT => KeyAction::TS,
+        0x0002..=0x00FF => KeyAction::CK(KbdKey::from_code(low)?),
+        0x2000..=0x3FFF => {
+            let modifier_key = modifier_of((keycode >> 8) & 0x1F)?;
+            KeyAction::UK(UncertKey::HK(modifier_key.into(), QwertyKey::from_code(low)?, VIA_TAPPING_TERM_MS))
+        },
+        0x4000..=0x4FFF => {
+            let layer = ((keycode >> 8) & 0x0F) as u8;
+            KeyAction::UK(UncertKey::HK(LayerKey::LayerOn(layer).into(), QwertyKey::from_code(low)?, VIA_TAPPING_TERM_MS))
+        },
+        0x5200..=0x521F => KeyAction::CK(LayerKey::LayerTo(layer).into()),
+        0x5220..=0x523F => KeyAction::CK(LayerKey::LayerOn(layer).into()),
+        0x5240..=0x525F => KeyAction::CK(LayerKey::DefaultLayer(layer).into()),
+        0x5260..=0x527F => KeyAction::CK(LayerKey::LayerSwitch(layer).into()),
+        0x5280..=0x529F => KeyAction::OS(LayerKey::LayerOn(layer).into()),
+        0x52A0..=0x52BF => KeyAction::OS(modifier_of(keycode & 0x1F)?.into()),
+        0x5700..=0x57FF => KeyAction::TD(low),
+        0x7700..=0x777F => KeyAction::DynMacro((keycode - QK_MACRO) as u8),
+        QK_AUTO_SHIFT_TOGGLE => KeyAction::AutoShiftToggle,
+        QK_LEADER => KeyAction::Leader,
+        QK_CAPS_WORD_TOGGLE => KeyAction::CapsWord,
+        QK_REPEAT_KEY => KeyAction::Repeat,
+        QK_ALT_REPEAT_KEY => KeyAction::AltRepeat,
+        0x7E00..=0x7E3F => KeyAction::Macro((keycode - QK_KB) as u8),
+        _ => return None,
+    };
+    Some(action)
+}
+
+pub fn unicode_mode_of(value: u8) -> Option<UnicodeMode> {
+    match value {
+        0 => Some(UnicodeMode::Linux),
+        1 => Some(UnicodeMode::WinAlt),
+        2 => Some(UnicodeMode::WinCompose),
+        3 => Some(UnicodeMode::MacOS),
+        _ => None,
+    }
+}
+
+/// VIA命令可以修改的键盘状态
+pub struct ViaTarget<'a, const KEY_NUM: usize, const LAYER_NUM: usize> {
+    pub key_map: &'a mut KeyMap<KEY_NUM, LAYER_NUM>,
+    /// 重置布局时恢复的布局
+    pub default_key_map: &'a KeyMap<KEY_NUM, LAYER_NUM>,
+    pub dynamic_macros: &'a mut DynamicMacros,
+    pub auto_shift: &'a mut AutoShift,
+    pub unicode_mode: &'a mut UnicodeMode,
+    /// 重置时恢复默认层
+    pub layer_state: &'a mut LayerState,
+}
+
+impl<const KEY_NUM: usize, const LAYER_NUM: usize> ViaTarget<'_, KEY_NUM, LAYER_NUM> {
+    const MATRIX_SIZE: usize = matrix_rows(KEY_NUM) * VIA_MATRIX_COLS;
+
+    /// 第`layer`层第`row`行第`col`列的按键动作，超出布局时返回`None`
+    fn action_mut(&mut self, layer: usize, row: usize, col: usize) -> Option<&mut KeyAction> {
+        if col >= VIA_MATRIX_COLS {
+            return None
+        }
+        self.key_map.get_mut(layer)?.get_mut(row * VIA_MATRIX_COLS + col)
+    }
+
+    /// 布局缓冲区中第`position`个键码，布局按层、行、列排列
+    fn keycode_at(&mut self, position: usize) -> u16 {
+        let (layer, index) = (position / Self::MATRIX_SIZE, position % Self::MATRIX_SIZE);
+        self.action_mut(layer, index / VIA_MATRIX_COLS, index % VIA_MATRIX_COLS)
+            .map_or(KC_NO, |action| keycode_of(action))
+    }
+
+    fn set_keycode_at(&mut self, position: usize, keycode: u16) {
+        let (layer, index) = (position / Self::MATRIX_SIZE, position % Self::MATRIX_SIZE);
+        self.set_keycode(layer, index / VIA_MATRIX_COLS, index % VIA_MATRIX_COLS, keycode);
+    }
+
+    fn set_keycode(&mut self, layer: usize, row: usize, col: usize, keycode: u16) {
+        if keycode == KC_UNSUPPORTED {
+            return
+        }
+        match (self.action_mut(layer, row, col), action_of(keycode)) {
+            (Some(slot), Some(action)) => *slot = action,
+            (None, _) => defmt::warn!("VIA key out of range: layer {}, row {}, col {}", layer, row, col),
+            (_, None) => defmt::warn!("Unsupported VIA keycode `{:#x}`", keycode),
+        }
+    }
+
+    /// 处理一条VIA命令，应答写回`packet`，不支持的命令将首字节改为`ID_UNHANDLED`
+    ///
+    /// 返回是否修改了需要保存的配置
+    pub fn process(&mut self, packet: &mut ViaPacket, now: Instant) -> bool {
+        let [id, data @ ..] = packet;
+        let modifies = matches!(
+            *id,
+            ID_DYNAMIC_KEYMAP_SET_KEYCODE | ID_DYNAMIC_KEYMAP_RESET | ID_CUSTOM_SET_VALUE | ID_CUSTOM_SAVE
+                | ID_EEPROM_RESET | ID_DYNAMIC_KEYMAP_MACRO_SET_BUFFER | ID_DYNAMIC_KEYMAP_MACRO_RESET
+                | ID_DYNAMIC_KEYMAP_SET_BUFFER
+        );
+        match *id {
+            ID_GET_PROTOCOL_VERSION => data[..2].copy_from_slice(&VIA_PROTOCOL_VERSION.to_be_bytes()),
+            ID_GET_KEYBOARD_VALUE => match data[0] {
+                ID_UPTIME => data[1..5].copy_from_slice(&(now.as_millis() as u32).to_be_bytes()),
+                ID_LAYOUT_OPTIONS => data[1..5].fill(0),
+                ID_FIRMWARE_VERSION => data[1..5].copy_from_slice(&VIA_FIRMWARE_VERSION.to_be_bytes()),
+                _ => *id = ID_UNHANDLED,
+            },
+            ID_SET_KEYBOARD_VALUE => match data[0] {
+                // 没有可选布局，直接忽略
+                ID_LAYOUT_OPTIONS => {},
+                _ => *id = ID_UNHANDLED,
+            },
+            ID_DYNAMIC_KEYMAP_GET_KEYCODE => {
+                let (layer, row, col) = (data[0] as usize, data[1] as usize, data[2] as usize);
+                let keycode = self.action_mut(layer, row, col).map_or(KC_NO, |action| keycode_of(action));
+                data[3..5].copy_from_slice(&keycode.to_be_bytes());
+            },
+            ID_DYNAMIC_KEYMAP_SET_KEYCODE => {
+                let keycode = u16::from_be_bytes([data[3], data[4]]);
+                self.set_keycode(data[0] as usize, data[1] as usize, data[2] as usize, keycode);
+            },
+            ID_DYNAMIC_KEYMAP_RESET => *self.key_map = *self.default_key_map,
+            ID_CUSTOM_SET_VALUE | ID_CUSTOM_GET_VALUE if data[0] != ID_CUSTOM_CHANNEL => *id = ID_UNHANDLED,
+            ID_CUSTOM_SET_VALUE => match (data[1], data[2]) {
+                (ID_CUSTOM_AUTO_SHIFT, value) => self.auto_shift.set_enabled(value != 0),
+                (ID_CUSTOM_UNICODE_MODE, value) if let Some(mode) = unicode_mode_of(value) => *self.unicode_mode = mode,
+                _ => *id = ID_UNHANDLED,
+            },
+            ID_CUSTOM_GET_VALUE => match data[1] {
+                ID_CUSTOM_AUTO_SHIFT => data[2] = self.auto_shift.is_enabled() as u8,
+                ID_CUSTOM_UNICODE_MODE => data[2] = *self.unicode_mode as u8,
+                _ => *id = ID_UNHANDLED,
+            },
+            // 设置即时生效，保存与其他修改一起进行
+            ID_CUSTOM_SAVE => {},
+            // 恢复所有保存的配置
+            ID_EEPROM_RESET => {
+                *self.key_map = *self.default_key_map;
+                self.dynamic_macros.reset();
+                self.auto_shift.set_enabled(AUTO_SHIFT_ENABLED);
+                *self.unicode_mode = UNICODE_DEFAULT_MODE;
+                self.layer_state.set_default_layer(0);
+            },
+            ID_DYNAMIC_KEYMAP_MACRO_GET_COUNT => data[0] = DYNAMIC_MACRO_COUNT,
+            ID_DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE => {
+                data[..2].copy_from_slice(&(self.dynamic_macros.buffer().len() as u16).to_be_bytes());
+            },
+            ID_DYNAMIC_KEYMAP_MACRO_GET_BUFFER | ID_DYNAMIC_KEYMAP_MACRO_SET_BUFFER => {
+                let (range, payload) = buffer_request(data);
+                if range.end > self.dynamic_macros.buffer().len() {
+                    *id = ID_UNHANDLED;
+                } else if *id == ID_DYNAMIC_KEYMAP_MACRO_SET_BUFFER {
+                    self.dynamic_macros.buffer_mut()[range].copy_from_slice(payload);
+                } else {
+                    payload.copy_from_slice(&self.dynamic_macros.buffer()[range]);
+                }
+            },
+            ID_DYNAMIC_KEYMAP_MACRO_RESET => self.dynamic_macros.reset(),
+            ID_DYNAMIC_KEYMAP_GET_LAYER_COUNT => data[0] = LAYER_NUM as u8,
+            ID_DYNAMIC_KEYMAP_GET_BUFFER => {
+                let (range, payload) = buffer_request(data);
+                for (index, byte) in range.zip(payload) {
+                    *byte = self.keycode_at(index / 2).to_be_bytes()[index % 2];
+                }
+            },
+            ID_DYNAMIC_KEYMAP_SET_BUFFER => {
+                let (range, payload) = buffer_request(data);
+                // 上位机按整个键码写入
+                if range.start % 2 != 0 || range.len() % 2 != 0 {
+                    *id = ID_UNHANDLED;
+                } else {
+                    for (index, bytes) in range.step_by(2).zip(payload.chunks_exact(2)) {
+                        self.set_keycode_at(index / 2, u16::from_be_bytes([bytes[0], bytes[1]]));
+                    }
+                }
+            },
+            _ => *id = ID_UNHANDLED,
+        }
+        modifies && *id != ID_UNHANDLED
+    }
+}
+
+/// 缓冲区读写命令的参数：16位偏移、长度，之后为数据
+fn buffer_request(data: &mut [u8]) -> (core::ops::Range<usize>, &mut [u8]) {
+    let offset = u16::from_be_bytes([data[0], data[1]]) as usize;
+    let size = (data[2] as usize).min(data.len() - 3);
+    (offset..offset + size, &mut data[3..3 + size])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::kbd::key::basic_key::*;
+    use crate::core::kbd::key_action::{ck, hk, sk};
+
+    const KEY_NUM: usize = VIA_MATRIX_COLS;
+
+    #[test]
+    fn hold_tap_keycodes_round_trip() {
+        let mod_tap = hk(LCtrl, A, VIA_TAPPING_TERM_MS);
+        let keycode = keycode_of(&mod_tap);
+        assert_eq!(keycode, QK_MOD_TAP | mod_bits(LCtrl) << 8 | A as u16);
+        assert_eq!(action_of(keycode), Some(mod_tap));
+
+        let layer_tap = hk(LayerOn(1), A, VIA_TAPPING_TERM_MS);
+        assert_eq!(action_of(keycode_of(&layer_tap)), Some(layer_tap));
+
+        // 读回后会变成不同的动作，视为不支持
+        assert_eq!(keycode_of(&sk(LCtrl, A)), KC_UNSUPPORTED);
+        assert_eq!(keycode_of(&hk(LCtrl, A, VIA_TAPPING_TERM_MS + 100)), KC_UNSUPPORTED);
+        assert_eq!(keycode_of(&sk(LayerOn(1), A)), KC_UNSUPPORTED);
+    }
+
+    #[test]
+    fn unsupported_keycode_keeps_action() {
+        let mut key_map: KeyMap<KEY_NUM, 1> = [[ck(A); KEY_NUM]];
+        key_map[0][1] = sk(LShift, B);
+        let default_key_map = key_map;
+        let mut dynamic_macros = DynamicMacros::default();
+        let mut auto_shift = AutoShift::default();
+        let mut unicode_mode = UnicodeMode::Linux;
+        let mut layer_state = LayerState::default();
+        let mut target = ViaTarget {
+            key_map: &mut key_map,
+            default_key_map: &default_key_map,
+            dynamic_macros: &mut dynamic_macros,
+            auto_shift: &mut auto_shift,
+            unicode_mode: &mut unicode_mode,
+            layer_state: &mut layer_state,
+        };
+
+        let mut packet = [0; VIA_PACKET_SIZE];
+        packet[..4].copy_from_slice(&[ID_DYNAMIC_KEYMAP_GET_KEYCODE, 0, 0, 1]);
+        target.process(&mut packet, Instant::from_millis(0));
+        assert_eq!(u16::from_be_bytes([packet[4], packet[5]]), KC_UNSUPPORTED);
+
+        // 上位机原样写回
+        packet[0] = ID_DYNAMIC_KEYMAP_SET_KEYCODE;
+        target.process(&mut packet, Instant::from_millis(0));
+        assert_eq!(key_map[0][1], sk(LShift, B));
+    }
+
+    #[test]
+    fn eeprom_reset_restores_settings() {
+        let default_key_map: KeyMap<KEY_NUM, 2> = [[ck(A); KEY_NUM]; 2];
+        let mut key_map = [[ck(B); KEY_NUM]; 2];
+        let mut dynamic_macros = DynamicMacros::default();
+        let mut auto_shift = AutoShift::default();
+        auto_shift.set_enabled(!AUTO_SHIFT_ENABLED);
+        let mut unicode_mode = UnicodeMode::MacOS;
+        let mut layer_state = LayerState::default();
+        layer_state.set_default_layer(1);
+        let mut target = ViaTarget {
+            key_map: &mut key_map,
+            default_key_map: &default_key_map,
+            dynamic_macros: &mut dynamic_macros,
+            auto_shift: &mut auto_shift,
+            unicode_mode: &mut unicode_mode,
+            layer_state: &mut layer_state,
+        };
+
+        let mut packet = [0; VIA_PACKET_SIZE];
+        packet[0] = ID_EEPROM_RESET;
+        assert!(target.process(&mut packet, Instant::from_millis(0)));
+        assert_eq!(key_map, default_key_map);
+        assert_eq!(auto_shift.is_enabled(), AUTO_SHIFT_ENABLED);
+        assert_eq!(unicode_mode, UNICODE_DEFAULT_MODE);
+        assert_eq!(layer_state.default_layer(), 0);
+    }
+
+    /// VIA定义中的矩阵大小与固件一致
+    #[test]
+    fn via_definition_matrix() {
+        let definition = include_str!("../../via/lint-kbd2.json");
+        let rows = matrix_rows(crate::key_map::KEY_NUM);
+        assert!(definition.contains(&format!(r#""matrix": {{"rows": {rows}, "cols": {VIA_MATRIX_COLS}}}"#)));
+    }
+}
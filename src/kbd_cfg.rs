@@ -1,26 +1,155 @@
 pub mod channel {
     pub const REPORT_CHANNEL_SIZE: usize = 16;
     pub const KEY_EVENT_CHANNEL_SIZE: usize = 32;
+    /// 主机LED状态的接收端数量(核心)，只查询最新状态不需要接收端
+    #[cfg(not(test))]
+    pub const HOST_LEDS_RECEIVER_NUM: usize = 1;
 }
 
+#[cfg(not(test))]
 pub mod usb {
     // 根据情况填写大小，BUFF_SIZE别写超过片上USB缓存就行
-    pub const CFG_DESC_SIZE: usize = 128;
+    pub const CFG_DESC_SIZE: usize = 192;
     pub const BOS_DESC_SIZE: usize = 32;
     pub const MSOS_DESC_SIZE: usize = 32;
     pub const USB_BUFF_SIZE: usize = 128;   // f103最大512B
 }
 
+pub mod indicator {
+    #[cfg(not(test))]
+    use crate::kbd_peripherals::indicator_led::{IndicatorCondition::*, IndicatorRule, LedPattern::*};
+
+    /// 指示灯刷新间隔(ms)
+    #[cfg(not(test))]
+    pub const INDICATOR_TICK_MS: u64 = 20;
+    /// 闪码中每次闪烁的亮灭时间及两轮之间的间隔(ms)
+    pub const BLINK_CODE_PULSE_MS: u16 = 200;
+    pub const BLINK_CODE_GAP_MS: u16 = 1_000;
+    /// 出错后指示的持续时间(ms)，期间再次出错重新计时
+    #[cfg(not(test))]
+    pub const ERROR_INDICATE_MS: u64 = 5_000;
+
+    /// 状态指示灯规则，按顺序匹配，都不满足时熄灭
+    #[cfg(not(test))]
+    pub const STATUS_LED_RULES: &[IndicatorRule] = &[
+        IndicatorRule::new(Error(crate::kbd_peripherals::indicator_led::ERROR_USB_WRITE), Code(3)),
+        IndicatorRule::new(Error(crate::kbd_peripherals::indicator_led::ERROR_STORAGE_WRITE), Code(4)),
+        IndicatorRule::new(UsbSuspended, Blink { on_ms: 100, off_ms: 1_900 }),
+        IndicatorRule::new(OneShot, Blink { on_ms: 150, off_ms: 150 }),
+        IndicatorRule::new(Layer(1), Blink { on_ms: 500, off_ms: 500 }),
+        IndicatorRule::new(CapsLock, On),
+    ];
+}
+
 pub mod core {
     /// 扫描频率
+    #[cfg(not(test))]
     pub const SCAN_FREQUENCY: u64 = 10_000;
 
     /// 消抖判决延迟(ms)，即至少要经过10ms判断出结果
     /// 
     /// 数值越高，按键越不灵敏，但相应的干扰跳动更少
+    #[cfg(not(test))]
     const DEBOUNCE_THRESHOLD_MS: u32 = 10;
 
     /// 消抖阈值，不懂不要修改
+    #[cfg(not(test))]
     pub const DEBOUNCE_THRESHOLD: u16 = ((SCAN_FREQUENCY*(DEBOUNCE_THRESHOLD_MS as u64)) / 1_000) as u16;
+
+    /// 轻击舞(Tap Dance)支持的最大连击次数
+    pub const TAP_DANCE_MAX_TAPS: usize = 3;
+
+    /// 同时生效的one-shot键数量上限
+    pub const ONE_SHOT_MAX: usize = 4;
+    /// one-shot键轻击后的失效时间(ms)
+    pub const ONE_SHOT_TIMEOUT_MS: u64 = 3_000;
+    /// 轻击one-shot键后再次按下是否锁定
+    pub const ONE_SHOT_TAP_TO_LOCK: bool = true;
+
+    /// 组合键(Combo)数量上限
+    pub const COMBO_MAX: usize = 16;
+    /// 单个组合键的成员键数量上限
+    pub const COMBO_MAX_KEYS: usize = 4;
+    /// 组合键默认判定窗口(ms)
+    pub const COMBO_TIMEOUT_MS: u16 = 50;
+
+    /// 默认的Unicode输入方式，可通过按键在运行时切换
+    pub const UNICODE_DEFAULT_MODE: crate::core::unicode::UnicodeMode = crate::core::unicode::UnicodeMode::Linux;
+
+    /// 上电时是否启用自动Shift，可通过按键在运行时开关
+    pub const AUTO_SHIFT_ENABLED: bool = false;
+    /// 自动Shift按住阈值(ms)，按住超过该时间输出Shift后的字符
+    pub const AUTO_SHIFT_TIMEOUT_MS: u64 = 175;
+    /// 同时待定的自动Shift按键数量上限
+    pub const AUTO_SHIFT_MAX: usize = 4;
+    /// 不启用自动Shift的按键
+    pub const AUTO_SHIFT_EXCLUDE: &[crate::core::kbd::key::QwertyKey] = &[];
+
+    /// Leader序列的最大长度
+    pub const LEADER_MAX_KEYS: usize = 4;
+    /// Leader捕获超时(ms)，每捕获一个按键重新计时
+    pub const LEADER_TIMEOUT_MS: u64 = 500;
+
+    /// Caps Word空闲超时(ms)
+    pub const CAPS_WORD_IDLE_TIMEOUT_MS: u64 = 5_000;
+    /// Caps Word中不加Shift、但不结束Caps Word的按键
+    pub const CAPS_WORD_CONTINUE: &[crate::core::kbd::key::QwertyKey] = {
+        use crate::core::kbd::key::QwertyKey::*;
+        &[Kc1, Kc2, Kc3, Kc4, Kc5, Kc6, Kc7, Kc8, Kc9, Kc0, Backspace, Delete]
+    };
+    /// 结束Caps Word的按键，优先级高于字母和`-`
+    pub const CAPS_WORD_BREAK: &[crate::core::kbd::key::QwertyKey] = &[];
+
+    /// 待定键判定期间最多记录的其他键按下数，超出时判定为按住
+    pub const HOLD_TAP_BUFFER_SIZE: usize = 8;
+
+    /// 鼠标键加速曲线
+    pub const MOUSE_KEYS_CURVE: crate::core::mouse_keys::MouseCurve = crate::core::mouse_keys::MouseCurve::Quadratic;
+    /// 鼠标键输出移动量的间隔(ms)
+    pub const MOUSE_KEYS_INTERVAL_MS: u64 = 16;
+    /// 鼠标键每次输出的最小/最大移动量
+    pub const MOUSE_KEYS_MIN_SPEED: u8 = 2;
+    pub const MOUSE_KEYS_MAX_SPEED: u8 = 20;
+    /// 按住方向键后加速到最大移动量的时间(ms)
+    pub const MOUSE_KEYS_TIME_TO_MAX_MS: u64 = 1000;
+    /// 按住`MouseAccel0~2`时的恒定移动量
+    pub const MOUSE_KEYS_CONSTANT_SPEEDS: [u8; 3] = [2, 8, 24];
+    /// 鼠标滚轮的输出间隔(ms)及每次滚动量
+    pub const MOUSE_WHEEL_INTERVAL_MS: u64 = 80;
+    pub const MOUSE_WHEEL_SPEED: i8 = 1;
+
+    /// 按键事件队列大小，包括待处理的事件和待定判定期间暂存的事件，暂存满时按超时结束判定
+    pub const EVENT_QUEUE_SIZE: usize = 16;
+
+    /// 最多同时登记的定时任务数，每种任务同时只登记一次，因此不小于`Task`的种类数
+    pub const SCHEDULER_SIZE: usize = 10;
+
+    /// VIA动态宏数量及宏缓冲区大小(字节)
+    pub const DYNAMIC_MACRO_COUNT: u8 = 16;
+    pub const DYNAMIC_MACRO_BUFFER_SIZE: usize = 512;
+}
+
+pub mod via {
+    /// VIA中按键矩阵的列数，按键索引`index`对应第`index/列数`行、第`index%列数`列
+    ///
+    /// 修改后需同步`via/lint-kbd2.json`中的`matrix`和按键标签。该定义未收录到VIA中，使用时在VIA的
+    /// Settings中打开`Show Design tab`，再在Design页面通过`Load Draft Definition`加载
+    pub const VIA_MATRIX_COLS: usize = 8;
+    /// 通过VIA设置的待定键(MT/LT)的按住判定时间(ms)
+    pub const VIA_TAPPING_TERM_MS: u16 = 200;
+    /// 固件版本，VIA中显示
+    pub const VIA_FIRMWARE_VERSION: u32 = 1;
 }
 
+
+pub mod storage {
+    /// 配置存储区分为2个分区，交替写入，每个分区的大小(字节)需为闪存页大小(F103为1KB)的整数倍
+    /// 存储区位于闪存末尾，共占用`2*STORAGE_BANK_SIZE`，需与`memory.x`中为程序保留的空间一致
+    pub const STORAGE_BANK_SIZE: u32 = 2048;
+    /// 存储格式版本，配置格式变化时需修改，版本不一致的配置加载时被忽略
+    pub const STORAGE_VERSION: u16 = 2;
+    /// 序列化配置的缓冲区大小
+    pub const SETTINGS_BUFFER_SIZE: usize = 1024;
+    /// 配置修改后等待该时间(ms)内没有新的修改才写入闪存，减少擦写次数
+    pub const SETTINGS_SAVE_DELAY_MS: u64 = 2000;
+}
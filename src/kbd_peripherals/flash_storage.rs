@@ -0,0 +1,411 @@
+// 闪存配置存储
+// 存储区分为2个分区，配置以记录的形式在当前分区中依次追加，分区写满后擦除另一个分区再写入，
+// 擦写分散到整个存储区，且写入中途断电时之前的记录仍然完整
+// 记录格式：记录头(魔数、版本、数据长度、序号、CRC32)+配置数据，按闪存写入单位对齐
+// 加载时选择序号最大的有效记录，通过embedded-storage的NorFlash访问闪存，与具体芯片无关
+
+use embedded_storage::nor_flash::{NorFlash, NorFlashError};
+
+use crate::core::settings::{SaveError, Settings, SettingsStore};
+use crate::core::KeyMap;
+use crate::kbd_cfg::storage::{SETTINGS_BUFFER_SIZE, STORAGE_VERSION};
+
+const RECORD_MAGIC: u16 = 0x4B53;
+/// 记录头：魔数(u16)、版本(u16)、数据长度(u16)、保留(u16)、序号(u32)、CRC32(u32)，小端
+const HEADER_SIZE: usize = 16;
+/// 擦除后的字节值
+const ERASED: u8 = 0xFF;
+
+/// CRC-32(IEEE)，逐位计算，代码体积小，可分段计算
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+struct Header {
+    version: u16,
+    len: u16,
+    seq: u32,
+    crc: u32,
+}
+
+impl Header {
+    fn new(payload: &[u8], seq: u32) -> Self {
+        let mut header = Self { version: STORAGE_VERSION, len: payload.len() as u16, seq, crc: 0 };
+        header.crc = header.compute_crc(payload);
+        header
+    }
+
+    fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[0..2].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.version.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.len.to_le_bytes());
+        bytes[6..8].fill(ERASED);
+        bytes[8..12].copy_from_slice(&self.seq.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+
+    /// 魔数不符时返回`None`
+    fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Option<Self> {
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        (u16_at(0) == RECORD_MAGIC).then(|| Self { version: u16_at(2), len: u16_at(4), seq: u32_at(8), crc: u32_at(12) })
+    }
+
+    /// CRC覆盖记录头中CRC之前的部分和配置数据
+    fn compute_crc(&self, payload: &[u8]) -> u32 {
+        crc32(crc32(0, &self.to_bytes()[..12]), payload)
+    }
+
+    fn is_valid(&self, payload: &[u8]) -> bool {
+        self.version == STORAGE_VERSION && self.crc == self.compute_crc(payload)
+    }
+}
+
+pub struct FlashStorage<F: NorFlash> {
+    flash: F,
+    /// 存储区的起始地址(相对闪存起始位置)
+    offset: u32,
+    bank_size: u32,
+    /// 当前写入的分区及分区中空闲空间的起始位置
+    bank: u32,
+    free: u32,
+    /// 最新记录的序号
+    seq: u32,
+}
+
+impl<F: NorFlash> FlashStorage<F> {
+    /// 存储区从`offset`开始，共2个`bank_size`大小的分区，均需按擦除单位对齐
+    pub fn new(flash: F, offset: u32, bank_size: u32) -> Self {
+        assert!((offset as usize).is_multiple_of(F::ERASE_SIZE) && (bank_size as usize).is_multiple_of(F::ERASE_SIZE));
+        assert!(HEADER_SIZE.is_multiple_of(F::WRITE_SIZE));
+        // 加载前视为当前分区已满，保存时写入擦除后的另一个分区
+        Self { flash, offset, bank_size, bank: 0, free: bank_size, seq: 0 }
+    }
+
+    fn bank_offset(&self, bank: u32) -> u32 {
+        self.offset + bank * self.bank_size
+    }
+
+    /// 记录占用的空间，按写入单位对齐
+    fn record_size(len: usize) -> u32 {
+        (HEADER_SIZE + len).next_multiple_of(F::WRITE_SIZE) as u32
+    }
+
+    /// 读取最新的有效记录到`buf`，返回数据长度，没有有效记录时返回`None`
+    ///
+    /// 同时确定之后追加记录的位置，保存前需先加载
+    pub fn load(&mut self, buf: &mut [u8]) -> Option<usize> {
+        // 最新记录的序号、地址、长度
+        let mut latest: Option<(u32, u32, usize)> = None;
+        for bank in 0..2 {
+            let (record, free) = self.scan_bank(bank, buf);
+            // 序号回绕后仍按写入顺序比较
+            if let Some(record) = record && latest.is_none_or(|(seq, ..)| record.0.wrapping_sub(seq) as i32 > 0) {
+                latest = Some(record);
+                (self.bank, self.free, self.seq) = (bank, free, record.0);
+            }
+        }
+
+        let (seq, address, len) = latest?;
+        match self.flash.read(address + HEADER_SIZE as u32, &mut buf[..len]) {
+            Ok(()) => {
+                defmt::info!("Settings loaded, seq {}", seq);
+                Some(len)
+            },
+            Err(e) => {
+                defmt::error!("Failed to read settings: {}", defmt::Debug2Format(&e.kind()));
+                None
+            },
+        }
+    }
+
+    /// 加载配置，没有有效记录或内容无效时使用以`default_key_map`为布局的默认配置
+    ///
+    /// `buf`为读取用的缓冲区，之后交给核心保存配置时复用
+    pub fn load_settings<const KEY_NUM: usize, const LAYER_NUM: usize>(
+        &mut self,
+        default_key_map: &KeyMap<KEY_NUM, LAYER_NUM>,
+        buf: &mut [u8; SETTINGS_BUFFER_SIZE],
+    ) -> Settings<KEY_NUM, LAYER_NUM> {
+        self.load(buf)
+            .and_then(|len| Settings::deserialize(&buf[..len], default_key_map))
+            .unwrap_or_else(|| Settings::new(*default_key_map))
+    }
+
+    /// 扫描分区中的记录，返回最新的有效记录(序号、地址、长度)和空闲空间的起始位置
+    fn scan_bank(&mut self, bank: u32, buf: &mut [u8]) -> (Option<(u32, u32, usize)>, u32) {
+        let start = self.bank_offset(bank);
+        let mut latest = None;
+        let mut pos = 0;
+        while pos + HEADER_SIZE as u32 <= self.bank_size {
+            let mut bytes = [0; HEADER_SIZE];
+            if self.flash.read(start + pos, &mut bytes).is_err() {
+                break
+            }
+            if bytes.iter().all(|&b| b == ERASED) {
+                return (latest, pos)
+            }
+            let Some(header) = Header::from_bytes(&bytes) else { break };
+            let len = header.len as usize;
+            let size = Self::record_size(len);
+            if len > buf.len() || pos + size > self.bank_size {
+                break
+            }
+
+            let payload = &mut buf[..len];
+            if self.flash.read(start + pos + HEADER_SIZE as u32, payload).is_ok() && header.is_valid(payload) {
+                latest = Some((header.seq, start + pos, len));
+            } else {
+                defmt::warn!("Invalid settings record at {:#x}", start + pos);
+            }
+            pos += size;
+        }
+        // 分区已满或有无法解析的数据，不能继续追加
+        (latest, self.bank_size)
+    }
+
+    /// 追加一条记录，当前分区放不下时擦除另一个分区并写入
+    fn write_record(&mut self, payload: &[u8]) -> Result<(), F::Error> {
+        let size = Self::record_size(payload.len());
+        if self.free + size > self.bank_size {
+            let bank = 1 - self.bank;
+            let start = self.bank_offset(bank);
+            self.flash.erase(start, start + self.bank_size)?;
+            (self.bank, self.free) = (bank, 0);
+        }
+
+        let seq = self.seq.wrapping_add(1);
+        let address = self.bank_offset(self.bank) + self.free;
+        // 先占用空间，写入失败时之后的记录写在其后
+        self.free += size;
+        self.flash.write(address, &Header::new(payload, seq).to_bytes())?;
+        let (body, tail) = payload.split_at(payload.len() / F::WRITE_SIZE * F::WRITE_SIZE);
+        self.flash.write(address + HEADER_SIZE as u32, body)?;
+        if !tail.is_empty() {
+            // 不足一个写入单位的部分用擦除值填充
+            let mut last = [ERASED; HEADER_SIZE];
+            last[..tail.len()].copy_from_slice(tail);
+            self.flash.write(address + (HEADER_SIZE + body.len()) as u32, &last[..F::WRITE_SIZE])?;
+        }
+        self.seq = seq;
+        Ok(())
+    }
+}
+
+impl<F: NorFlash> SettingsStore for FlashStorage<F> {
+    fn save(&mut self, payload: &[u8]) -> Result<(), SaveError> {
+        if Self::record_size(payload.len()) > self.bank_size {
+            return Err(SaveError::TooLarge)
+        }
+        match self.write_record(payload) {
+            Ok(()) => {
+                defmt::info!("Settings saved, seq {}", self.seq);
+                Ok(())
+            },
+            Err(e) => {
+                defmt::warn!("Failed to write settings record: {}", defmt::Debug2Format(&e.kind()));
+                Err(SaveError::Write)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+    use super::*;
+    use crate::core::kbd::key::basic_key::*;
+    use crate::core::kbd::key_action::ck;
+    use crate::core::settings;
+    use crate::key_map::{custom_key_map, Settings};
+    use crate::kbd_cfg::storage::STORAGE_BANK_SIZE;
+
+    /// 存储区前留一个擦除单位，检查不会越界写入
+    const OFFSET: u32 = 1024;
+
+    /// RAM模拟的闪存，写入单位2字节，擦除单位1KB，只能写入已擦除的位置
+    struct RamFlash {
+        data: Vec<u8>,
+        erases: usize,
+    }
+
+    impl RamFlash {
+        fn new() -> Self {
+            Self { data: vec![ERASED; (OFFSET + 2 * STORAGE_BANK_SIZE) as usize], erases: 0 }
+        }
+    }
+
+    impl ErrorType for RamFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for RamFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let start = offset as usize;
+            let data = self.data.get(start..start + bytes.len()).ok_or(NorFlashErrorKind::OutOfBounds)?;
+            bytes.copy_from_slice(data);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for RamFlash {
+        const WRITE_SIZE: usize = 2;
+        const ERASE_SIZE: usize = 1024;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            assert!(from >= OFFSET, "erase outside storage");
+            self.erases += 1;
+            self.data[from as usize..to as usize].fill(ERASED);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            assert!(offset >= OFFSET, "write outside storage");
+            assert!((offset as usize).is_multiple_of(Self::WRITE_SIZE) && bytes.len().is_multiple_of(Self::WRITE_SIZE));
+            let data = &mut self.data[offset as usize..offset as usize + bytes.len()];
+            assert!(data.iter().all(|&b| b == ERASED), "write to non-erased flash");
+            data.copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    fn open(flash: &mut RamFlash) -> FlashStorage<&mut RamFlash> {
+        FlashStorage::new(flash, OFFSET, STORAGE_BANK_SIZE)
+    }
+
+    /// 修改一个按键后的配置
+    fn modified(key: usize) -> Settings {
+        let mut settings = Settings::new(custom_key_map());
+        settings.key_map[0][key] = ck(F13);
+        settings.auto_shift_enabled = !settings.auto_shift_enabled;
+        settings.default_layer = 1;
+        settings
+    }
+
+    fn save(storage: &mut FlashStorage<&mut RamFlash>, settings: &Settings) {
+        let mut buf = [0; SETTINGS_BUFFER_SIZE];
+        let size = settings::serialize(
+            &settings.key_map,
+            &settings.dynamic_macros,
+            settings.auto_shift_enabled,
+            settings.unicode_mode,
+            settings.default_layer,
+            &mut buf,
+        );
+        assert_eq!(storage.save(&buf[..size]), Ok(()));
+    }
+
+    fn load(flash: &mut RamFlash) -> Settings {
+        open(flash).load_settings(&custom_key_map(), &mut [0; SETTINGS_BUFFER_SIZE])
+    }
+
+    fn assert_settings_eq(actual: &Settings, expected: &Settings) {
+        assert!(actual.key_map == expected.key_map);
+        assert_eq!(actual.dynamic_macros.buffer(), expected.dynamic_macros.buffer());
+        assert_eq!(actual.auto_shift_enabled, expected.auto_shift_enabled);
+        assert_eq!(actual.unicode_mode, expected.unicode_mode);
+        assert_eq!(actual.default_layer, expected.default_layer);
+    }
+
+    /// 最新记录的配置数据位置
+    fn latest_payload_at(flash: &mut RamFlash) -> usize {
+        let mut storage = open(flash);
+        let mut buf = [0; SETTINGS_BUFFER_SIZE];
+        let len = storage.load(&mut buf).unwrap();
+        let record = FlashStorage::<&mut RamFlash>::record_size(len);
+        (storage.bank_offset(storage.bank) + storage.free - record) as usize + HEADER_SIZE
+    }
+
+    #[test]
+    fn empty_storage_loads_default() {
+        let mut flash = RamFlash::new();
+        assert_settings_eq(&load(&mut flash), &Settings::new(custom_key_map()));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut flash = RamFlash::new();
+        let settings = modified(1);
+        let mut storage = open(&mut flash);
+        storage.load_settings(&custom_key_map(), &mut [0; SETTINGS_BUFFER_SIZE]);
+        save(&mut storage, &settings);
+        assert_settings_eq(&load(&mut flash), &settings);
+    }
+
+    #[test]
+    fn bank_swap() {
+        let mut flash = RamFlash::new();
+        let record = FlashStorage::<&mut RamFlash>::record_size(Settings::SIZE);
+        let per_bank = (STORAGE_BANK_SIZE / record) as usize;
+        // 每次重新加载后再保存，写满当前分区后换到另一个分区
+        for key in 0..3 * per_bank {
+            let mut storage = open(&mut flash);
+            storage.load_settings(&custom_key_map(), &mut [0; SETTINGS_BUFFER_SIZE]);
+            save(&mut storage, &modified(key));
+            assert_settings_eq(&load(&mut flash), &modified(key));
+        }
+        // 首次保存时擦除1次，之后每写满一个分区擦除1次
+        assert_eq!(flash.erases, 3);
+    }
+
+    #[test]
+    fn seq_wrap_around() {
+        let mut flash = RamFlash::new();
+        let record = FlashStorage::<&mut RamFlash>::record_size(Settings::SIZE);
+        let per_bank = (STORAGE_BANK_SIZE / record) as usize;
+        let mut storage = open(&mut flash);
+        storage.load_settings(&custom_key_map(), &mut [0; SETTINGS_BUFFER_SIZE]);
+        // 写满一个分区时序号到达u32::MAX，下一条记录回绕到0并写入另一个分区
+        storage.seq = u32::MAX - per_bank as u32;
+        for key in 0..=per_bank {
+            save(&mut storage, &modified(key));
+        }
+        assert_eq!(storage.seq, 0);
+        assert_settings_eq(&load(&mut flash), &modified(per_bank));
+    }
+
+    #[test]
+    fn corrupted_record_loads_default() {
+        let mut flash = RamFlash::new();
+        let mut storage = open(&mut flash);
+        storage.load_settings(&custom_key_map(), &mut [0; SETTINGS_BUFFER_SIZE]);
+        save(&mut storage, &modified(1));
+
+        let at = latest_payload_at(&mut flash);
+        flash.data[at] ^= 0x01;
+        assert_settings_eq(&load(&mut flash), &Settings::new(custom_key_map()));
+    }
+
+    #[test]
+    fn version_mismatch_loads_default() {
+        let mut flash = RamFlash::new();
+        let mut storage = open(&mut flash);
+        storage.load_settings(&custom_key_map(), &mut [0; SETTINGS_BUFFER_SIZE]);
+        save(&mut storage, &modified(1));
+
+        // 按旧版本格式重写记录头(CRC有效)
+        let at = latest_payload_at(&mut flash);
+        let payload = flash.data[at..at + Settings::SIZE].to_vec();
+        let mut header = Header::new(&payload, 1);
+        header.version = STORAGE_VERSION.wrapping_add(1);
+        header.crc = header.compute_crc(&payload);
+        flash.data[at - HEADER_SIZE..at].copy_from_slice(&header.to_bytes());
+        assert_settings_eq(&load(&mut flash), &Settings::new(custom_key_map()));
+    }
+}
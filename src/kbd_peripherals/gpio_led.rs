@@ -0,0 +1,28 @@
+// GPIO指示灯
+// 依赖芯片HAL，主机上运行测试时不编译
+
+use embassy_stm32::gpio;
+
+use super::indicator_led::IndicatorPin;
+
+/// GPIO指示灯，`active_low`为`true`时低电平点亮
+pub struct GpioLed<'d> {
+    pin: gpio::Output<'d>,
+    active_low: bool,
+}
+
+impl<'d> GpioLed<'d> {
+    pub fn new(pin: gpio::Output<'d>, active_low: bool) -> Self {
+        Self { pin, active_low }
+    }
+}
+
+impl IndicatorPin for GpioLed<'_> {
+    fn set_on(&mut self, on: bool) {
+        if on != self.active_low {
+            self.pin.set_high();
+        } else {
+            self.pin.set_low();
+        }
+    }
+}
@@ -0,0 +1,292 @@
+// LED指示灯
+// 将键盘状态(主机LED、激活层、one-shot、USB挂起、错误)映射为LED显示模式(常亮、闪烁、闪码)
+// 每个指示灯按规则顺序匹配当前状态，模式计算只依赖传入的状态和时间，引脚通过`IndicatorPin`抽象
+// 与芯片HAL无关，GPIO实现见`gpio_led`
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_time::{Duration, Instant};
+
+use crate::core::host_leds::HostLeds;
+use crate::kbd_cfg::indicator::{BLINK_CODE_GAP_MS, BLINK_CODE_PULSE_MS};
+#[cfg(not(test))]
+use {
+    embassy_time::Ticker,
+    crate::core::host_leds::host_leds,
+    crate::core::status,
+    crate::kbd_cfg::indicator::{ERROR_INDICATE_MS, INDICATOR_TICK_MS},
+};
+
+/// 错误码，用于区分不同的闪码
+#[cfg(not(test))]
+pub const ERROR_USB_WRITE: u8 = 1;
+pub const ERROR_STORAGE_WRITE: u8 = 2;
+
+/// 最近一次登记的错误码，0表示没有新错误
+static ERROR_CODE: AtomicU8 = AtomicU8::new(0);
+
+/// 登记错误，指示灯在之后的`ERROR_INDICATE_MS`内显示对应的错误状态
+pub fn indicate_error(code: u8) {
+    ERROR_CODE.store(code, Ordering::Release);
+}
+
+/// 指示灯引脚，与具体的GPIO、极性无关
+pub trait IndicatorPin {
+    fn set_on(&mut self, on: bool);
+}
+
+/// 显示模式
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedPattern {
+    Off,
+    On,
+    /// 按固定亮灭时间闪烁
+    Blink { on_ms: u16, off_ms: u16 },
+    /// 闪码：连续闪烁n次后熄灭一段时间，循环
+    Code(u8),
+}
+
+impl LedPattern {
+    /// 模式开始`elapsed`后是否点亮
+    pub fn is_on(&self, elapsed: Duration) -> bool {
+        let t = elapsed.as_millis();
+        match *self {
+            LedPattern::Off => false,
+            LedPattern::On => true,
+            LedPattern::Blink { on_ms, off_ms } => {
+                let period = on_ms as u64 + off_ms as u64;
+                period == 0 || t % period < on_ms as u64
+            },
+            LedPattern::Code(count) => {
+                let pulse = BLINK_CODE_PULSE_MS as u64;
+                let pulses = 2 * pulse * count as u64;
+                let t = t % (pulses + BLINK_CODE_GAP_MS as u64);
+                t < pulses && (t / pulse).is_multiple_of(2)
+            },
+        }
+    }
+}
+
+/// 指示灯显示的条件
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndicatorCondition {
+    CapsLock,
+    NumLock,
+    ScrollLock,
+    /// 指定层激活(包括默认层)
+    Layer(u8),
+    /// 有等待作用的one-shot键
+    OneShot,
+    UsbSuspended,
+    /// 出现指定错误码的错误
+    Error(u8),
+}
+
+/// 条件满足时显示`pattern`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndicatorRule {
+    pub condition: IndicatorCondition,
+    pub pattern: LedPattern,
+}
+
+impl IndicatorRule {
+    pub const fn new(condition: IndicatorCondition, pattern: LedPattern) -> Self {
+        Self { condition, pattern }
+    }
+}
+
+/// 指示灯关心的键盘状态
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndicatorState {
+    pub host_leds: HostLeds,
+    pub layer_bits: u32,
+    pub one_shot_armed: bool,
+    pub usb_suspended: bool,
+    /// 正在指示的错误码
+    pub error: Option<u8>,
+}
+
+impl IndicatorCondition {
+    fn matches(&self, state: &IndicatorState) -> bool {
+        match *self {
+            IndicatorCondition::CapsLock => state.host_leds.caps_lock(),
+            IndicatorCondition::NumLock => state.host_leds.num_lock(),
+            IndicatorCondition::ScrollLock => state.host_leds.scroll_lock(),
+            IndicatorCondition::Layer(layer) => (state.layer_bits >> layer) & 1 == 1,
+            IndicatorCondition::OneShot => state.one_shot_armed,
+            IndicatorCondition::UsbSuspended => state.usb_suspended,
+            IndicatorCondition::Error(code) => state.error == Some(code),
+        }
+    }
+}
+
+pub struct Indicator<P: IndicatorPin> {
+    pin: P,
+    /// 按顺序匹配的规则，都不满足时熄灭
+    rules: &'static [IndicatorRule],
+    /// 当前模式及其开始时间，模式切换时从头开始显示
+    pattern: LedPattern,
+    since: Instant,
+    is_on: bool,
+}
+
+impl<P: IndicatorPin> Indicator<P> {
+    pub fn new(mut pin: P, rules: &'static [IndicatorRule]) -> Self {
+        pin.set_on(false);
+        Self { pin, rules, pattern: LedPattern::Off, since: Instant::MIN, is_on: false }
+    }
+
+    /// 根据状态选择模式并刷新引脚，仅在亮灭变化时写引脚
+    pub fn update(&mut self, state: &IndicatorState, now: Instant) {
+        let pattern = self.rules.iter()
+            .find(|rule| rule.condition.matches(state))
+            .map_or(LedPattern::Off, |rule| rule.pattern);
+        if pattern != self.pattern {
+            self.pattern = pattern;
+            self.since = now;
+        }
+
+        let is_on = self.pattern.is_on(now - self.since);
+        if is_on != self.is_on {
+            self.pin.set_on(is_on);
+            self.is_on = is_on;
+        }
+    }
+}
+
+/// 按固定间隔采集键盘状态并刷新所有指示灯
+#[cfg(not(test))]
+pub async fn run_indicators<P: IndicatorPin, const N: usize>(mut indicators: [Indicator<P>; N]) {
+    let mut ticker = Ticker::every(Duration::from_millis(INDICATOR_TICK_MS));
+    // 正在指示的错误码及结束时间
+    let mut error: Option<(u8, Instant)> = None;
+    loop {
+        let now = Instant::now();
+        let code = ERROR_CODE.swap(0, Ordering::AcqRel);
+        if code != 0 {
+            error = Some((code, now + Duration::from_millis(ERROR_INDICATE_MS)));
+        }
+        error = error.filter(|&(_, until)| now < until);
+
+        let state = IndicatorState {
+            host_leds: host_leds(),
+            layer_bits: status::layer_bits(),
+            one_shot_armed: status::one_shot_armed(),
+            usb_suspended: super::usb::usb_suspended(),
+            error: error.map(|(code, _)| code),
+        };
+        for indicator in indicators.iter_mut() {
+            indicator.update(&state, now);
+        }
+        ticker.next().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use IndicatorCondition::*;
+
+    /// 记录每次写入的引脚
+    #[derive(Default)]
+    struct MockPin {
+        writes: std::vec::Vec<bool>,
+    }
+
+    impl IndicatorPin for &mut MockPin {
+        fn set_on(&mut self, on: bool) {
+            self.writes.push(on);
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn blink_timing() {
+        let pattern = LedPattern::Blink { on_ms: 100, off_ms: 300 };
+        assert!(pattern.is_on(ms(0)));
+        assert!(pattern.is_on(ms(99)));
+        assert!(!pattern.is_on(ms(100)));
+        assert!(!pattern.is_on(ms(399)));
+        assert!(pattern.is_on(ms(400)));
+        // 周期为0时常亮
+        assert!(LedPattern::Blink { on_ms: 0, off_ms: 0 }.is_on(ms(123)));
+    }
+
+    #[test]
+    fn code_timing() {
+        let pulse = BLINK_CODE_PULSE_MS as u64;
+        let cycle = 4 * pulse + BLINK_CODE_GAP_MS as u64;
+        let pattern = LedPattern::Code(2);
+        // 亮、灭、亮、灭，之后熄灭一段间隔
+        assert!(pattern.is_on(ms(0)));
+        assert!(!pattern.is_on(ms(pulse)));
+        assert!(pattern.is_on(ms(2 * pulse)));
+        assert!(!pattern.is_on(ms(3 * pulse)));
+        assert!(!pattern.is_on(ms(4 * pulse)));
+        assert!(!pattern.is_on(ms(cycle - 1)));
+        assert!(pattern.is_on(ms(cycle)));
+    }
+
+    #[test]
+    fn update_rule_priority() {
+        static RULES: &[IndicatorRule] = &[
+            IndicatorRule::new(Error(1), LedPattern::Off),
+            IndicatorRule::new(CapsLock, LedPattern::On),
+            IndicatorRule::new(Layer(1), LedPattern::Blink { on_ms: 100, off_ms: 100 }),
+        ];
+        let mut pin = MockPin::default();
+        let mut indicator = Indicator::new(&mut pin, RULES);
+        let t0 = Instant::from_millis(1_000);
+        let mut state = IndicatorState { layer_bits: 0b11, ..Default::default() };
+
+        // 只满足层规则
+        indicator.update(&state, t0);
+        assert_eq!(indicator.pattern, LedPattern::Blink { on_ms: 100, off_ms: 100 });
+        assert!(indicator.is_on);
+
+        // 前面的规则优先
+        state.host_leds = HostLeds(HostLeds::CAPS_LOCK);
+        indicator.update(&state, t0 + ms(150));
+        assert_eq!(indicator.pattern, LedPattern::On);
+        state.error = Some(1);
+        indicator.update(&state, t0 + ms(200));
+        assert_eq!(indicator.pattern, LedPattern::Off);
+        assert!(!indicator.is_on);
+
+        // 都不满足时熄灭
+        let state = IndicatorState::default();
+        indicator.update(&state, t0 + ms(300));
+        assert_eq!(indicator.pattern, LedPattern::Off);
+        // 初始化熄灭，之后仅在亮灭变化时写引脚
+        assert_eq!(pin.writes, [false, true, false]);
+    }
+
+    #[test]
+    fn update_restarts_pattern() {
+        static RULES: &[IndicatorRule] = &[
+            IndicatorRule::new(OneShot, LedPattern::Blink { on_ms: 100, off_ms: 100 }),
+            IndicatorRule::new(Layer(0), LedPattern::Blink { on_ms: 50, off_ms: 50 }),
+        ];
+        let mut pin = MockPin::default();
+        let mut indicator = Indicator::new(&mut pin, RULES);
+        let t0 = Instant::from_millis(1_000);
+        let mut state = IndicatorState { layer_bits: 1, ..Default::default() };
+
+        indicator.update(&state, t0);
+        indicator.update(&state, t0 + ms(60));
+        assert!(!indicator.is_on);
+        // 模式切换后从头开始显示
+        state.one_shot_armed = true;
+        indicator.update(&state, t0 + ms(60));
+        assert!(indicator.is_on);
+        indicator.update(&state, t0 + ms(159));
+        assert!(indicator.is_on);
+        indicator.update(&state, t0 + ms(160));
+        assert!(!indicator.is_on);
+    }
+}
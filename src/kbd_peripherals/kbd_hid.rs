@@ -0,0 +1,205 @@
+// 键盘HID接口
+// embassy-usb的HID类固定子类和协议为0，且拒绝切换到启动协议，BIOS等只支持启动协议的主机无法识别
+// 这里按启动键盘(子类1，协议1)注册接口，并自行处理HID类请求：
+// 1. SET_PROTOCOL/GET_PROTOCOL切换和查询报文格式，结果通过`core::kbd_report`告知核心
+// 2. SET_REPORT/GET_REPORT/SET_IDLE/GET_IDLE交给`hid::RequestHandler`处理
+
+use static_cell::StaticCell;
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{class::hid, Builder, Handler};
+
+use crate::core::kbd_report::{is_boot_protocol, set_boot_protocol};
+
+const USB_CLASS_HID: u8 = 0x03;
+const USB_SUBCLASS_BOOT: u8 = 0x01;
+const USB_PROTOCOL_KEYBOARD: u8 = 0x01;
+
+const HID_DESC_DESCTYPE_HID: u8 = 0x21;
+const HID_DESC_DESCTYPE_HID_REPORT: u8 = 0x22;
+
+const HID_REQ_GET_REPORT: u8 = 0x01;
+const HID_REQ_GET_IDLE: u8 = 0x02;
+const HID_REQ_GET_PROTOCOL: u8 = 0x03;
+const HID_REQ_SET_REPORT: u8 = 0x09;
+const HID_REQ_SET_IDLE: u8 = 0x0a;
+const HID_REQ_SET_PROTOCOL: u8 = 0x0b;
+
+/// 协议值，0为启动协议，1为报告协议
+const PROTOCOL_BOOT: u16 = 0;
+const PROTOCOL_REPORT: u16 = 1;
+
+pub struct Config {
+    /// 报告协议下的报文描述符，启动协议下主机不读取描述符，直接按固定格式解析
+    pub report_descriptor: &'static [u8],
+    pub request_handler: Option<&'static mut (dyn hid::RequestHandler + 'static)>,
+    /// 轮询延迟(ms)
+    pub poll_ms: u8,
+    pub max_packet_size: u16,
+}
+
+pub struct KbdHIDWriter<'d, D: Driver<'d>> {
+    ep_in: D::EndpointIn,
+}
+
+impl<'d, D: Driver<'d>> KbdHIDWriter<'d, D> {
+    /// 发送一个报文，报文长度不能超过`max_packet_size`
+    pub async fn write(&mut self, report: &[u8]) -> Result<(), EndpointError> {
+        self.ep_in.write(report).await
+    }
+}
+
+pub struct KbdHIDReader<'d, D: Driver<'d>> {
+    ep_out: D::EndpointOut,
+}
+
+impl<'d, D: Driver<'d>> KbdHIDReader<'d, D> {
+    /// 读取一个输出报文，返回报文长度
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        self.ep_out.wait_enabled().await;
+        self.ep_out.read(buf).await
+    }
+}
+
+/// 创建键盘HID接口，并将请求处理注册到USB中
+pub fn new<D: Driver<'static>>(
+    builder: &mut Builder<'static, D>,
+    config: Config,
+) -> (KbdHIDReader<'static, D>, KbdHIDWriter<'static, D>) {
+    let len = config.report_descriptor.len();
+    let hid_descriptor = [
+        // 描述符长度和类型
+        9, HID_DESC_DESCTYPE_HID,
+        // HID版本1.10
+        0x10, 0x01,
+        // 不指定国家代码
+        0x00,
+        // 之后有1个描述符，即报文描述符
+        1,
+        HID_DESC_DESCTYPE_HID_REPORT,
+        (len & 0xFF) as u8,
+        (len >> 8 & 0xFF) as u8,
+    ];
+
+    let mut func = builder.function(USB_CLASS_HID, USB_SUBCLASS_BOOT, USB_PROTOCOL_KEYBOARD);
+    let mut iface = func.interface();
+    let if_num = iface.interface_number();
+    let mut alt = iface.alt_setting(USB_CLASS_HID, USB_SUBCLASS_BOOT, USB_PROTOCOL_KEYBOARD, None);
+    alt.descriptor(HID_DESC_DESCTYPE_HID, &hid_descriptor[2..]);
+    let ep_in = alt.endpoint_interrupt_in(None, config.max_packet_size, config.poll_ms);
+    let ep_out = alt.endpoint_interrupt_out(None, config.max_packet_size, config.poll_ms);
+    drop(func);
+
+    static CONTROL: StaticCell<Control> = StaticCell::new();
+    builder.handler(CONTROL.init(Control {
+        if_num,
+        report_descriptor: config.report_descriptor,
+        request_handler: config.request_handler,
+        hid_descriptor,
+    }));
+
+    (KbdHIDReader { ep_out }, KbdHIDWriter { ep_in })
+}
+
+struct Control {
+    if_num: InterfaceNumber,
+    report_descriptor: &'static [u8],
+    request_handler: Option<&'static mut (dyn hid::RequestHandler + 'static)>,
+    /// 完整的HID描述符(含长度和类型)，用于GET_DESCRIPTOR
+    hid_descriptor: [u8; 9],
+}
+
+fn report_id(value: u16) -> Option<hid::ReportId> {
+    match value >> 8 {
+        1 => Some(hid::ReportId::In(value as u8)),
+        2 => Some(hid::ReportId::Out(value as u8)),
+        3 => Some(hid::ReportId::Feature(value as u8)),
+        _ => None,
+    }
+}
+
+impl Handler for Control {
+    fn reset(&mut self) {
+        // 复位后默认使用报告协议
+        set_boot_protocol(false);
+    }
+
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if (req.request_type, req.recipient, req.index)
+            != (RequestType::Class, Recipient::Interface, self.if_num.0 as u16)
+        {
+            return None
+        }
+
+        match req.request {
+            HID_REQ_SET_PROTOCOL => match req.value {
+                PROTOCOL_BOOT | PROTOCOL_REPORT => {
+                    defmt::info!("HID protocol set to {}", if req.value == PROTOCOL_BOOT { "boot" } else { "report" });
+                    set_boot_protocol(req.value == PROTOCOL_BOOT);
+                    Some(OutResponse::Accepted)
+                },
+                _ => Some(OutResponse::Rejected),
+            },
+            HID_REQ_SET_IDLE => {
+                if let Some(handler) = self.request_handler.as_mut() {
+                    let id = req.value as u8;
+                    let id = (id != 0).then_some(hid::ReportId::In(id));
+                    let dur = u32::from(req.value >> 8);
+                    let dur = if dur == 0 { u32::MAX } else { 4 * dur };
+                    handler.set_idle_ms(id, dur);
+                }
+                Some(OutResponse::Accepted)
+            },
+            HID_REQ_SET_REPORT => match (report_id(req.value), self.request_handler.as_mut()) {
+                (Some(id), Some(handler)) => Some(handler.set_report(id, data)),
+                _ => Some(OutResponse::Rejected),
+            },
+            _ => Some(OutResponse::Rejected),
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if req.index != self.if_num.0 as u16 {
+            return None
+        }
+
+        match (req.request_type, req.recipient) {
+            (RequestType::Standard, Recipient::Interface) => match req.request {
+                Request::GET_DESCRIPTOR => match (req.value >> 8) as u8 {
+                    HID_DESC_DESCTYPE_HID_REPORT => Some(InResponse::Accepted(self.report_descriptor)),
+                    HID_DESC_DESCTYPE_HID => Some(InResponse::Accepted(&self.hid_descriptor)),
+                    _ => Some(InResponse::Rejected),
+                },
+                _ => Some(InResponse::Rejected),
+            },
+            (RequestType::Class, Recipient::Interface) => match req.request {
+                HID_REQ_GET_PROTOCOL => {
+                    buf[0] = if is_boot_protocol() { PROTOCOL_BOOT } else { PROTOCOL_REPORT } as u8;
+                    Some(InResponse::Accepted(&buf[0..1]))
+                },
+                HID_REQ_GET_IDLE => {
+                    let id = req.value as u8;
+                    let id = (id != 0).then_some(hid::ReportId::In(id));
+                    match self.request_handler.as_mut().and_then(|handler| handler.get_idle_ms(id)) {
+                        Some(dur) => {
+                            buf[0] = u8::try_from(dur / 4).unwrap_or(0);
+                            Some(InResponse::Accepted(&buf[0..1]))
+                        },
+                        None => Some(InResponse::Rejected),
+                    }
+                },
+                HID_REQ_GET_REPORT => {
+                    let size = report_id(req.value)
+                        .and_then(|id| self.request_handler.as_mut()?.get_report(id, buf));
+                    match size {
+                        Some(size) => Some(InResponse::Accepted(&buf[0..size])),
+                        None => Some(InResponse::Rejected),
+                    }
+                },
+                _ => Some(InResponse::Rejected),
+            },
+            _ => None,
+        }
+    }
+}
@@ -2,15 +2,17 @@ pub mod debounce;
 pub mod key_state;
 
 use defmt::error;
+#[cfg(not(test))]
 use embassy_stm32 as stm32;
-use stm32::gpio;
-use stm32::spi;
+#[cfg(not(test))]
+use stm32::{gpio, spi};
 
 use crate::core::channel::KEY_EVENT_CHANNEL;
 use crate::core::kbd::key_event::KeyEvent;
 use debounce::{DebounceKeyStates, KeyDiff};
 use key_state::BitKeyStates;
 
+#[cfg(not(test))]
 /// 基于74H165的按键扫描方案
 /// 
 /// 注意SPI扫描频率写死了，泛型里的扫描频率是按键完整扫描一遍的频率
@@ -25,6 +27,7 @@ pub(crate) struct SPIKeyScanner<
     key_states: DStates,
 }
 
+#[cfg(not(test))]
 impl<
     'd,
     DStates: DebounceKeyStates<BitKeyStates<KEY_NUM>, BitKeyStates<KEY_NUM>> + Default,
@@ -91,6 +94,7 @@ impl<
             }
             let read_buf = BitKeyStates::<KEY_NUM>::from_buffer(read_buf);
             let diff = self.key_states.debounce(&read_buf);
+            let now = Instant::now();
 
             for index in 0..KEY_NUM {
                 if diff.is_different(index) {
@@ -98,7 +102,7 @@ impl<
                     let key_event = {
                         let is_pressed = self.key_states.is_pressed(index);
                         let index = index as u8;
-                        KeyEvent::new(is_pressed, index)
+                        KeyEvent::new(is_pressed, index, now)
                     };
                     KEY_EVENT_CHANNEL.send(key_event).await;
                 }
@@ -2,7 +2,16 @@
 // 仅实现USB，按键扫描读取和去抖提供API(方便使用其他扫描方案和去抖方法)
 // 通过channel传送按键事件给core处理
 
+// 测试在主机上运行，依赖芯片HAL或只在固件中使用的模块不参与编译
+#[cfg(not(test))]
 pub mod usb;
+#[cfg(not(test))]
+pub mod kbd_hid;
+#[cfg(not(test))]
 pub mod key_scanner;
-// TODO(L): 添加LED指示灯
-pub mod indicator_led;
\ No newline at end of file
+// LED指示灯
+pub mod indicator_led;
+#[cfg(not(test))]
+pub mod gpio_led;
+// 闪存配置存储
+pub mod flash_storage;
\ No newline at end of file
@@ -2,15 +2,24 @@ use core::sync::atomic::{AtomicBool, Ordering};
 
 use defmt::{error, info};
 use static_cell::StaticCell;
+#[cfg(not(test))]
 use embassy_stm32 as stm32;
 use embassy_sync::signal::Signal;
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
-use embassy_usb::{driver::EndpointError, driver::Driver, class::hid, *};
+use embassy_usb::{driver::EndpointError, driver::Driver, class::hid, control::OutResponse, *};
 
-use crate::core::channel::KEYBOARD_REPORT_CHANNEL;
+use crate::core::channel::{
+    EXTRA_REPORT_CHANNEL, KEYBOARD_REPORT_CHANNEL, MOUSE_REPORT_CHANNEL, VIA_REQUEST_CHANNEL, VIA_RESPONSE_CHANNEL,
+};
+use crate::core::extra_report::EXTRA_REPORT_SIZE;
+use crate::core::kbd_report::KBD_REPORT_SIZE;
+use crate::core::host_leds::publish_host_leds;
+use crate::core::via::{ViaPacket, VIA_PACKET_SIZE};
+use super::indicator_led::{indicate_error, ERROR_USB_WRITE};
 
 
 pub(crate) static USB_CONNECTED: AtomicBool = AtomicBool::new(false);
+pub(crate) static USB_SUSPENDED: AtomicBool = AtomicBool::new(false);
 pub(crate) static NEED_WAKEUP_REMOTE: Signal<ThreadModeRawMutex, ()> = Signal::new();
 
 pub fn set_usb_connected(conneted: bool) {
@@ -21,7 +30,16 @@ pub fn usb_connected() -> bool {
     USB_CONNECTED.load(Ordering::Acquire)
 }
 
+pub fn set_usb_suspended(suspended: bool) {
+    USB_SUSPENDED.store(suspended, Ordering::Release);
+}
+
+pub fn usb_suspended() -> bool {
+    USB_SUSPENDED.load(Ordering::Acquire)
+}
 
+
+#[cfg(not(test))]
 pub async fn force_usb_reset(usb_dp_pin: stm32::Peri<'_, impl stm32::gpio::Pin>) {
     use stm32::gpio;
     let _dp = gpio::Output::new(usb_dp_pin, gpio::Level::Low, gpio::Speed::Low);
@@ -50,40 +68,229 @@ pub fn get_usb_builder<
     )
 }
 
-pub type KbdHIDReader<'a, D> = hid::HidReader<'a, D, 1>;
-pub type KbdHIDWriter<'a, D> = hid::HidWriter<'a, D, 8>;
+pub use super::kbd_hid::{KbdHIDReader, KbdHIDWriter};
+
+/// 全键无冲键盘报文描述符，报文格式见`core::kbd_report`
+const NKRO_REPORT_DESC: &[u8] = &[
+    0x05, 0x01,         // Usage Page (Generic Desktop)
+    0x09, 0x06,         // Usage (Keyboard)
+    0xA1, 0x01,         // Collection (Application)
+    // 修饰键，8位
+    0x05, 0x07,         //   Usage Page (Keyboard/Keypad)
+    0x19, 0xE0,         //   Usage Minimum (0xE0)
+    0x29, 0xE7,         //   Usage Maximum (0xE7)
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x25, 0x01,         //   Logical Maximum (1)
+    0x75, 0x01,         //   Report Size (1)
+    0x95, 0x08,         //   Report Count (8)
+    0x81, 0x02,         //   Input (Data, Variable, Absolute)
+    // 按键位图，键码0x00~0xDF各占1位
+    0x19, 0x00,         //   Usage Minimum (0)
+    0x29, 0xDF,         //   Usage Maximum (0xDF)
+    0x95, 0xE0,         //   Report Count (224)
+    0x81, 0x02,         //   Input (Data, Variable, Absolute)
+    // LED输出报文，5位LED + 3位填充，与启动协议一致
+    0x05, 0x08,         //   Usage Page (LEDs)
+    0x19, 0x01,         //   Usage Minimum (Num Lock)
+    0x29, 0x05,         //   Usage Maximum (Kana)
+    0x95, 0x05,         //   Report Count (5)
+    0x91, 0x02,         //   Output (Data, Variable, Absolute)
+    0x75, 0x03,         //   Report Size (3)
+    0x95, 0x01,         //   Report Count (1)
+    0x91, 0x01,         //   Output (Constant)
+    0xC0,               // End Collection
+];
 
 /// 创建HID读写器，需传入usb_builder以将HID实例注册到USB中
+///
+/// 接口声明为启动键盘，报告协议下使用全键无冲报文，主机切换到启动协议时使用6键报文
 pub fn create_hid_reader_writer<D: Driver<'static>>(
     usb_device_builder: &mut Builder<'static, D>,
     request_handler: Option<&'static mut (dyn hid::RequestHandler + 'static)>
 ) -> (KbdHIDReader<'static, D>, KbdHIDWriter<'static, D>) {
-    use usbd_hid::descriptor::{SerializedDescriptor, KeyboardReport};
-    let kbd_hid_cfg = hid::Config {
-        // 报文描述符，用于描述报文格式。键盘报文描述符是固定的，直接调包
-        // TODO(L): 自定义Report，实现全键无冲？
-        report_descriptor: KeyboardReport::desc(),
+    let kbd_hid_cfg = super::kbd_hid::Config {
+        // 报文描述符，用于描述报告协议下的报文格式
+        report_descriptor: NKRO_REPORT_DESC,
         request_handler: request_handler,
         // 轮询延迟(ms)，1ms时对应1kHz回报率
         poll_ms: 1,
         max_packet_size: 64
     };
 
-    static KBD_HID_STATE: StaticCell<hid::State> = StaticCell::new();
+    super::kbd_hid::new(usb_device_builder, kbd_hid_cfg)
+}
+
+/// 处理键盘接口的控制请求，主机通过SET_REPORT下发LED输出报文
+///
+/// 部分主机(如启动协议下的BIOS)只通过控制端点下发LED状态，不走OUT端点
+pub struct HostLedsHandler;
+
+impl hid::RequestHandler for HostLedsHandler {
+    fn set_report(&mut self, id: hid::ReportId, data: &[u8]) -> OutResponse {
+        match (id, data.first()) {
+            (hid::ReportId::Out(0), Some(&leds)) => {
+                publish_host_leds(leds);
+                OutResponse::Accepted
+            },
+            _ => OutResponse::Rejected,
+        }
+    }
+}
+
+/// 创建键盘接口的请求处理器
+pub fn create_request_handler() -> &'static mut HostLedsHandler {
+    static HOST_LEDS_HANDLER: StaticCell<HostLedsHandler> = StaticCell::new();
+    HOST_LEDS_HANDLER.init(HostLedsHandler)
+}
+
+pub type MouseHIDWriter<'a, D> = hid::HidWriter<'a, D, 8>;
+
+/// 创建鼠标HID写入器，鼠标键使用独立的HID接口
+pub fn create_mouse_hid_writer<D: Driver<'static>>(
+    usb_device_builder: &mut Builder<'static, D>,
+) -> MouseHIDWriter<'static, D> {
+    use usbd_hid::descriptor::{SerializedDescriptor, MouseReport};
+    let mouse_hid_cfg = hid::Config {
+        report_descriptor: MouseReport::desc(),
+        request_handler: None,
+        poll_ms: 1,
+        max_packet_size: 8
+    };
+
+    static MOUSE_HID_STATE: StaticCell<hid::State> = StaticCell::new();
 
-    hid::HidReaderWriter::<_, 1, 8>::new(
+    hid::HidWriter::<_, 8>::new(
         usb_device_builder,
-        KBD_HID_STATE.init(hid::State::new()),
-        kbd_hid_cfg)
-    .split()
+        MOUSE_HID_STATE.init(hid::State::new()),
+        mouse_hid_cfg)
+}
+
+/// 非键盘报文的描述符，每种报文对应一个报文ID(见`core::extra_report`)
+const EXTRA_REPORT_DESC: &[u8] = &[
+    // Consumer Control，报文ID 1，16位用法
+    0x05, 0x0C,         // Usage Page (Consumer)
+    0x09, 0x01,         // Usage (Consumer Control)
+    0xA1, 0x01,         // Collection (Application)
+    0x85, 0x01,         //   Report ID (1)
+    0x19, 0x01,         //   Usage Minimum (1)
+    0x2A, 0xFF, 0xFF,   //   Usage Maximum (0xFFFF)
+    0x15, 0x01,         //   Logical Minimum (1)
+    0x27, 0xFF, 0xFF, 0x00, 0x00, //   Logical Maximum (0xFFFF)，4字节以免被当作负数
+    0x95, 0x01,         //   Report Count (1)
+    0x75, 0x10,         //   Report Size (16)
+    0x81, 0x00,         //   Input (Data, Array, Absolute)
+    0xC0,               // End Collection
+    // System Control，报文ID 2，16位用法，Windows要求用法从0x81开始
+    0x05, 0x01,         // Usage Page (Generic Desktop)
+    0x09, 0x80,         // Usage (System Control)
+    0xA1, 0x01,         // Collection (Application)
+    0x85, 0x02,         //   Report ID (2)
+    0x19, 0x81,         //   Usage Minimum (0x81)
+    0x2A, 0xB7, 0x00,   //   Usage Maximum (0xB7)
+    0x15, 0x81,         //   Logical Minimum (0x81)
+    0x26, 0xB7, 0x00,   //   Logical Maximum (0xB7)
+    0x95, 0x01,         //   Report Count (1)
+    0x75, 0x10,         //   Report Size (16)
+    0x81, 0x00,         //   Input (Data, Array, Absolute)
+    0xC0,               // End Collection
+];
+
+pub type ExtraHIDWriter<'a, D> = hid::HidWriter<'a, D, 8>;
+
+/// 创建非键盘报文(媒体键等)的HID写入器，不同报文通过报文ID区分
+pub fn create_extra_hid_writer<D: Driver<'static>>(
+    usb_device_builder: &mut Builder<'static, D>,
+) -> ExtraHIDWriter<'static, D> {
+    let extra_hid_cfg = hid::Config {
+        report_descriptor: EXTRA_REPORT_DESC,
+        request_handler: None,
+        poll_ms: 1,
+        max_packet_size: 8
+    };
+
+    static EXTRA_HID_STATE: StaticCell<hid::State> = StaticCell::new();
+
+    hid::HidWriter::<_, 8>::new(
+        usb_device_builder,
+        EXTRA_HID_STATE.init(hid::State::new()),
+        extra_hid_cfg)
+}
+
+/// VIA使用的Raw HID描述符，用法页和用法与QMK一致，上位机据此识别接口
+const RAW_REPORT_DESC: &[u8] = &[
+    0x06, 0x60, 0xFF,   // Usage Page (Vendor Defined 0xFF60)
+    0x09, 0x61,         // Usage (0x61)
+    0xA1, 0x01,         // Collection (Application)
+    // 设备到主机，32字节
+    0x09, 0x62,         //   Usage (0x62)
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x26, 0xFF, 0x00,   //   Logical Maximum (255)
+    0x95, 0x20,         //   Report Count (32)
+    0x75, 0x08,         //   Report Size (8)
+    0x81, 0x02,         //   Input (Data, Variable, Absolute)
+    // 主机到设备，32字节
+    0x09, 0x63,         //   Usage (0x63)
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x26, 0xFF, 0x00,   //   Logical Maximum (255)
+    0x95, 0x20,         //   Report Count (32)
+    0x75, 0x08,         //   Report Size (8)
+    0x91, 0x02,         //   Output (Data, Variable, Absolute)
+    0xC0,               // End Collection
+];
+
+pub type RawHIDReaderWriter<'a, D> = hid::HidReaderWriter<'a, D, VIA_PACKET_SIZE, VIA_PACKET_SIZE>;
+
+/// 创建VIA使用的Raw HID读写器
+pub fn create_raw_hid_reader_writer<D: Driver<'static>>(
+    usb_device_builder: &mut Builder<'static, D>,
+) -> RawHIDReaderWriter<'static, D> {
+    let raw_hid_cfg = hid::Config {
+        report_descriptor: RAW_REPORT_DESC,
+        request_handler: None,
+        poll_ms: 1,
+        max_packet_size: VIA_PACKET_SIZE as u16
+    };
+
+    static RAW_HID_STATE: StaticCell<hid::State> = StaticCell::new();
+
+    hid::HidReaderWriter::<_, VIA_PACKET_SIZE, VIA_PACKET_SIZE>::new(
+        usb_device_builder,
+        RAW_HID_STATE.init(hid::State::new()),
+        raw_hid_cfg)
+}
+
+/// 发送报文，设备挂起时先唤醒主机再重发
+async fn write_with_wakeup(mut write: impl AsyncFnMut() -> Result<(), EndpointError>) {
+    if let Err(e) = write().await {
+        if e != EndpointError::Disabled {
+            error!("Failed to send report: {:?}", e);
+            indicate_error(ERROR_USB_WRITE);
+            return;
+        }
+
+        NEED_WAKEUP_REMOTE.signal(());
+        // Wait 200ms for the wakeup, then send the report again
+        // Ignore the error for the second send
+        embassy_time::Timer::after_millis(200).await;
+        if let Err(e) = write().await {
+            error!("Failed to send report after wakeup: {:?}", e);
+            indicate_error(ERROR_USB_WRITE);
+        }
+    }
 }
 
 pub async fn run_usb<
     D: driver::Driver<'static>
 >(
     mut usb_device: UsbDevice<'static, D>,
-    mut hid_writer: KbdHIDWriter<'static, D>
+    mut hid_reader: KbdHIDReader<'static, D>,
+    mut hid_writer: KbdHIDWriter<'static, D>,
+    mut mouse_hid_writer: MouseHIDWriter<'static, D>,
+    mut extra_hid_writer: ExtraHIDWriter<'static, D>,
+    raw_hid: RawHIDReaderWriter<'static, D>,
 ) {
+    let (mut raw_hid_reader, mut raw_hid_writer) = raw_hid.split();
+
     loop {
         set_usb_connected(true);
 
@@ -96,6 +303,7 @@ pub async fn run_usb<
 
                 usb_device.run_until_suspend().await;
                 info!("USB suspend，wating for wakeup.");
+                set_usb_suspended(true);
                 // 设备被挂起了，两种情况下唤醒USB
                 match select(usb_device.wait_resume(), NEED_WAKEUP_REMOTE.wait()).await {
                     // 1. remote 主动恢复，resume USB
@@ -104,6 +312,7 @@ pub async fn run_usb<
                     // 交给USB底层实现，直接发包能触发wakeup remote?
                     Either::Second(_) => info!("USB wakeup remote"),
                 }
+                set_usb_suspended(false);
             }
         };
 
@@ -116,30 +325,86 @@ pub async fn run_usb<
                     continue;
                 };
 
-                if let Err(e) = hid_writer.write_serialize(&report).await {
-                    if e != EndpointError::Disabled {
-                        error!("Failed to send report: {:?}", e);
+                let mut buf = [0; KBD_REPORT_SIZE];
+                let len = report.serialize(&mut buf);
+                write_with_wakeup(async || hid_writer.write(&buf[..len]).await).await;
+            }
+        };
+
+        // 读取主机通过OUT端点下发的LED输出报文
+        let kbd_led_fut = async {
+            let mut buf = [0; 8];
+            loop {
+                match hid_reader.read(&mut buf).await {
+                    Ok(len) if len > 0 => publish_host_leds(buf[0]),
+                    // 端点禁用(挂起、复位)时等待重新启用
+                    Ok(_) | Err(EndpointError::Disabled) => {},
+                    Err(e) => error!("Failed to read output report: {:?}", e),
+                }
+            }
+        };
+
+        let mouse_hid_fut = async {
+            loop {
+                let report = MOUSE_REPORT_CHANNEL.receive().await;
+                if !usb_connected() {
+                    continue;
+                };
+
+                write_with_wakeup(async || mouse_hid_writer.write_serialize(&report).await).await;
+            }
+        };
+
+        let extra_hid_fut = async {
+            loop {
+                let report = EXTRA_REPORT_CHANNEL.receive().await;
+                if !usb_connected() {
+                    continue;
+                };
+
+                let mut buf = [0; EXTRA_REPORT_SIZE];
+                let len = report.serialize(&mut buf);
+                write_with_wakeup(async || extra_hid_writer.write(&buf[..len]).await).await;
+            }
+        };
+
+        // VIA命令交给核心处理，应答原样发回
+        let raw_hid_fut = async {
+            let mut packet: ViaPacket = [0; VIA_PACKET_SIZE];
+            loop {
+                match raw_hid_reader.read(&mut packet).await {
+                    Ok(_) => {},
+                    Err(hid::ReadError::BufferOverflow) => {
+                        error!("VIA packet is too long");
+                        continue;
+                    },
+                    Err(hid::ReadError::Disabled) => {
+                        raw_hid_reader.ready().await;
                         continue;
-                    }
-
-                    NEED_WAKEUP_REMOTE.signal(());
-                    // Wait 200ms for the wakeup, then send the report again
-                    // Ignore the error for the second send
-                    embassy_time::Timer::after_millis(200).await;
-                    if let Err(e) = hid_writer.write_serialize(&report).await {
-                        error!("Failed to send report after wakeup: {:?}", e);
-                    }
+                    },
+                    Err(hid::ReadError::Sync(_)) => continue,
                 }
+                VIA_REQUEST_CHANNEL.send(packet).await;
+                let response = VIA_RESPONSE_CHANNEL.receive().await;
+                write_with_wakeup(async || raw_hid_writer.write(&response).await).await;
             }
         };
 
         use futures::FutureExt;
         let mut usb_device_task = core::pin::pin!(usb_device_fut.fuse());
         let mut kbd_hid_task = core::pin::pin!(kbd_hid_fut.fuse());
+        let mut kbd_led_task = core::pin::pin!(kbd_led_fut.fuse());
+        let mut mouse_hid_task = core::pin::pin!(mouse_hid_fut.fuse());
+        let mut extra_hid_task = core::pin::pin!(extra_hid_fut.fuse());
+        let mut raw_hid_task = core::pin::pin!(raw_hid_fut.fuse());
 
         futures::select_biased! {
             _ = usb_device_task => error!("USB device task has ended"),
             _ = kbd_hid_task => error!("Keyboard HID task has ended"),
+            _ = kbd_led_task => error!("Keyboard LED task has ended"),
+            _ = mouse_hid_task => error!("Mouse HID task has ended"),
+            _ = extra_hid_task => error!("Extra HID task has ended"),
+            _ = raw_hid_task => error!("Raw HID task has ended"),
         };
 
         set_usb_connected(false);
@@ -1,3 +1,5 @@
+#[cfg(not(test))]
+use crate::core::KeyMapTables;
 use crate::core::kbd::key_action::KeyAction;
 
 /// 按键数量
@@ -6,8 +8,15 @@ pub const KEY_NUM: usize = 55;
 
 /// 按键层数
 pub const LAYER_NUM: usize = 4;
+// 层状态使用u32位图
+static_assertions::const_assert!(LAYER_NUM <= 32);
+// 组合键使用KEY_NUM之后的虚拟按键索引
+static_assertions::const_assert!(KEY_NUM + crate::kbd_cfg::core::COMBO_MAX <= 256);
 
 pub type KeyMap = super::core::KeyMap<KEY_NUM, LAYER_NUM>;
+pub type Settings = super::core::settings::Settings<KEY_NUM, LAYER_NUM>;
+// 序列化后的配置需能放入缓冲区
+static_assertions::const_assert!(Settings::SIZE <= crate::kbd_cfg::storage::SETTINGS_BUFFER_SIZE);
 pub type LogicalIndices = [usize; KEY_NUM];
 
 // 逻辑位置到物理连线的映射(注意EDA上的元件标号是从1开始的，放这里需要改成从0开始)
@@ -33,6 +42,43 @@ pub fn custom_key_map() -> KeyMap {
     physical_map(key_map)
 }
 
+/// 布局附带的功能表，默认都为空，需要时参照注释中的例子添加
+#[cfg(not(test))]
+pub fn custom_tables() -> KeyMapTables {
+    // 各表统一用StaticCell在运行时初始化(配置的构造函数不是const fn)，例：
+    // static HOLD_TAPS: StaticCell<[HoldTap; 1]> = StaticCell::new();
+    // let hold_taps = HOLD_TAPS.init([HoldTap::new(LCtrl, F, 200)]);
+    // 然后将`hold_taps: &[]`改为`hold_taps`
+    KeyMapTables {
+        // 待定键，例：主行修饰键，轻击F，按住LCtrl，快速打字时不触发
+        // HoldTap::new(LCtrl, F, 200).flavor(HoldTapFlavor::Balanced).quick_tap(150).prior_idle(100)
+        hold_taps: &[],
+        // 轻击舞，例：单击Esc，双击CapsLock，按住启用层2
+        // TapDance::new(200).tap(1, Escape).hold(1, LayerOn(2)).tap(2, CapsLock)
+        tap_dances: &[],
+        // 组合键，例：Combo::new(&physical_indices(&[3, 4]), ck(Escape))
+        combos: &[],
+        // 宏，例：&[MacroStep::Text("git status"), MacroStep::Tap(Enter.into())]
+        macros: &[],
+        // Leader键序列，例：LeaderSequence::new(&[G, S], mc(0))
+        leader_sequences: &[],
+        // 按键覆盖，例：Shift+Backspace发送Delete
+        // KeyOverride::new(mods(&[LShift]), Backspace, Delete, 0)
+        key_overrides: &[],
+        // 条件层，例：lower(1)+raise(2)=adjust(3)
+        // LayerRule::tri_layer(1, 2, 3)
+        layer_rules: &[],
+        // 反向重复键对，例：AltRepeat::new(Left, Right)、AltRepeat::new(Kc9, Kc0)(Shift时为`(`/`)`)
+        alt_repeats: &[],
+    }
+}
+
+/// 将逻辑位置转换为物理按键索引，用于组合键等直接引用按键的配置
+#[allow(unused)]
+pub fn physical_indices<const N: usize>(logical_indices: &[usize; N]) -> [u8; N] {
+    logical_indices.map(|index| PHYSICAL_INDICES[index] as u8)
+}
+
 pub fn default_key_map() -> KeyMap {
     [[KeyAction::NA; _]; _]
 }
@@ -1,5 +1,5 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(generic_const_exprs)]
 #![allow(incomplete_features)]
 
@@ -14,20 +14,26 @@ mod core;
 mod key_map;
 
 // logger
+#[cfg(not(test))]
 use {defmt_rtt as _, panic_probe as _};
 
+#[cfg(not(test))]
 use embassy_stm32 as stm32;
-use stm32::peripherals;
-use stm32::usb;
+#[cfg(not(test))]
+use stm32::{peripherals, usb};
 
-use kbp::key_scanner::key_state::BitKeyStates;
+#[cfg(not(test))]
+use kbp::key_scanner::{SPIKeyScanner, key_state::BitKeyStates};
+#[cfg(not(test))]
 use core::kbd::debounce::PingPongKeyStates;
-use kbp::key_scanner::SPIKeyScanner;
 
+#[cfg(not(test))]
 use kbd_cfg::core::*;
+#[cfg(not(test))]
 use key_map::KEY_NUM;
 
 // 中断向量表
+#[cfg(not(test))]
 stm32::bind_interrupts!(struct Irqs {
     // 触发usb中断，交给USB Handler处理
     // 这个中断是USB_LP/CAN1_RX0，即低速USB中断/CAN1 RX0中断
@@ -35,6 +41,7 @@ stm32::bind_interrupts!(struct Irqs {
     USB_LP_CAN1_RX0 => usb::InterruptHandler<peripherals::USB>;
 });
 
+#[cfg(not(test))]
 /// 创建外设配置，需要根据硬件配置初始化外设
 fn mcu_config() -> stm32::Config {
     let mut config = stm32::Config::default();
@@ -70,6 +77,7 @@ fn mcu_config() -> stm32::Config {
     config
 }
 
+#[cfg(not(test))]
 #[embassy_executor::main]
 async fn main(_spawner: embassy_executor::Spawner) {
     // # 初始化外设
@@ -106,6 +114,7 @@ async fn main(_spawner: embassy_executor::Spawner) {
         // vid表可以去USB-IF上翻，随便选一个不出现在上面的数就行
         // 如果电脑识别不到，说明配置的(vid,pid)冲突了，再重新找
         // let usb_cfg = embassy_usb::Config::new(11451,41919);
+        // 修改后需同步VIA定义`via/lint-kbd2.json`中的vendorId和productId
         let mut usb_cfg = embassy_usb::Config::new(0x63DD,0x0001);
         usb_cfg.manufacturer = Some("lint-kbd");
         usb_cfg.product = Some("lint-kbd2");
@@ -116,10 +125,17 @@ async fn main(_spawner: embassy_executor::Spawner) {
         kbp::usb::get_usb_builder(usb_driver, usb_cfg)
     };
 
-    // 创建KeyboardReport HID ReaderWriter
+    // 创建键盘HID ReaderWriter
     // 同时在USB接口描述符里添加该HID的描述符
-    // TODO(H): 添加request_handler，处理set_report操作对应的LED
-    let (_hid_reader, hid_writer) = kbp::usb::create_hid_reader_writer(&mut usb_device_builder, None);
+    // request_handler处理主机通过SET_REPORT下发的LED状态
+    let request_handler = kbp::usb::create_request_handler();
+    let (hid_reader, hid_writer) = kbp::usb::create_hid_reader_writer(&mut usb_device_builder, Some(request_handler));
+    // 鼠标键使用的鼠标HID
+    let mouse_hid_writer = kbp::usb::create_mouse_hid_writer(&mut usb_device_builder);
+    // 媒体键等非键盘报文使用的HID
+    let extra_hid_writer = kbp::usb::create_extra_hid_writer(&mut usb_device_builder);
+    // VIA配置使用的Raw HID
+    let raw_hid = kbp::usb::create_raw_hid_reader_writer(&mut usb_device_builder);
 
 
     // # 创建SPI按键扫描驱动
@@ -128,17 +144,67 @@ async fn main(_spawner: embassy_executor::Spawner) {
         SPIKeyScanner::new_blocking(mcu_peri.SPI2, mcu_peri.PB13, mcu_peri.PB14, mcu_peri.PB15);
 
 
+    // # 创建指示灯
+    // 状态指示灯，引脚和极性按实际PCB修改(这里用PC13，低电平点亮)
+    let status_led = {
+        use stm32::gpio;
+        let pin = gpio::Output::new(mcu_peri.PC13, gpio::Level::High, gpio::Speed::Low);
+        kbp::indicator_led::Indicator::new(kbp::gpio_led::GpioLed::new(pin, true), kbd_cfg::indicator::STATUS_LED_RULES)
+    };
+
+
+    // # 加载配置
+    // 配置保存在闪存末尾，没有有效配置(首次启动、数据损坏、格式版本变化)时使用默认布局
+    let default_key_map = key_map::custom_key_map();
+    let mut flash_storage = {
+        use embedded_storage::nor_flash::ReadNorFlash;
+        use kbd_cfg::storage::STORAGE_BANK_SIZE;
+        let flash = stm32::flash::Flash::new_blocking(mcu_peri.FLASH);
+        let offset = flash.capacity() as u32 - 2 * STORAGE_BANK_SIZE;
+        kbp::flash_storage::FlashStorage::new(flash, offset, STORAGE_BANK_SIZE)
+    };
+    // 加载和之后保存配置共用一个缓冲区，不占用栈空间
+    static SETTINGS_BUFFER: static_cell::ConstStaticCell<[u8; kbd_cfg::storage::SETTINGS_BUFFER_SIZE]> =
+        static_cell::ConstStaticCell::new([0; _]);
+    let settings_buf = SETTINGS_BUFFER.take();
+    let settings = flash_storage.load_settings(&default_key_map, settings_buf);
+    type FlashStorage = kbp::flash_storage::FlashStorage<stm32::flash::Flash<'static, stm32::flash::Blocking>>;
+    static FLASH_STORAGE: static_cell::StaticCell<FlashStorage> = static_cell::StaticCell::new();
+    let settings_store = FLASH_STORAGE.init(flash_storage);
+
+
     // # 创建键盘核心
-    let kbd_core = core::KbdCore::new(key_map::custom_key_map());
+    let kbd_core = core::KbdCore::new(settings, default_key_map, key_map::custom_tables(), settings_store, settings_buf);
 
 
     // # 启动
-    embassy_futures::join::join3(
+    embassy_futures::join::join4(
         // USB通信
-        kbp::usb::run_usb(usb_device_builder.build(), hid_writer),
+        kbp::usb::run_usb(usb_device_builder.build(), hid_reader, hid_writer, mouse_hid_writer, extra_hid_writer, raw_hid),
         // 按键扫描
         spi_key_device.run(),
         // 键盘核心，基于Channel和事件驱动
         kbd_core.run(),
+        // 指示灯
+        kbp::indicator_led::run_indicators([status_led]),
     ).await;
 }
+
+// 主机上测试时丢弃defmt日志
+#[cfg(test)]
+mod test_defmt {
+    #[defmt::global_logger]
+    struct Logger;
+
+    unsafe impl defmt::Logger for Logger {
+        fn acquire() {}
+        unsafe fn flush() {}
+        unsafe fn release() {}
+        unsafe fn write(_bytes: &[u8]) {}
+    }
+
+    #[defmt::panic_handler]
+    fn panic() -> ! {
+        panic!()
+    }
+}